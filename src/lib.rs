@@ -4,395 +4,4885 @@
 
     Implements functions:
 
-    place_for_sale - Accepts ID, and price from token owner, puts in tokens_for_sale. In the event that a token already put up for sale is transferred, the price of the token must be re-recorded
+    place_for_sale - Accepts ID, and price from token owner, puts in tokens_for_sale together with the seller. A token that is already for sale is rejected, its price is changed with update_price
 
-    withdraw - Withdraws a token from sale, i.e. removes a token with a price from tokens_for_sale
+    place_for_sale_batch - Same as place_for_sale for several tokens at once. If any of them fails the checks, none of them are listed
 
-    purchase - Must be called by the owner of the contract, provide the token that was successfully paid for, and the address of who paid to send him the token. If successful, the token is removed from sale
+    withdraw - Withdraws a token from sale, i.e. removes a token with a price from tokens_for_sale. Can be called by the seller or the marketplace admin
+
+    prune_listing - Can be called by anyone to remove a listing whose seller no longer owns the token
+
+    gift_batch - Transfers listed tokens from the seller to the given recipients for free and withdraws them from sale. Can be called by the seller
+
+    purchase - Must be called by the marketplace admin with the price of the token attached, provide the token, and the address of who paid to send him the token. If successful, the token is transferred from the seller, removed from sale and the payment, minus the marketplace commission and the collection royalty, is forwarded to the seller. The marketplace must be an operator of the seller, otherwise the purchase is rejected
+
+    purchase_batch - Like purchase for several tokens at once, the attached amount must equal the sum of their prices and the whole batch is rejected if any token cannot be bought
+
+    buy - Can be called by any account with the price of the token attached. The token is transferred from the seller to the buyer, which requires the marketplace to be an operator of the seller, and the payment is forwarded like in purchase
+
+    make_offer, cancel_offer, accept_offer - Buyers can offer CCD for any token, the amount is held by the contract until the offer is cancelled and refunded, or accepted by the token owner and paid out like a purchase
 
     view_list_for_sale - Shows the entire list of tokens for sale
+
+    Listings are identified by the NFT contract together with the token ID, so tokens from several collections can be listed at once. Only collections allowed by the admin can be listed.
+
+    Prices are paid in CCD by default. The admin can set a CIS2 payment token with set_payment_token, then purchase and buy transfer the price from the sender on the payment token contract instead, and no CCD is attached.
 */
 
 use concordium_cis2::*;
 use concordium_std::*;
 
-#[derive(Serialize, Debug, PartialEq, Eq, Reject)]
+#[derive(Serialize, Debug, PartialEq, Eq)]
 enum MarketplaceError {
-    #[from(ParseError)]
     ParseParams,
-    TokenNotFound,
+    TokenNotFound(TokenId),
     Unauthorized,
     InvokeContractError,
+    InvokeTransferError,
+    NotTokenOwner,
+    InsufficientPayment,
+    LogFull,
+    LogMalformed,
+    InvalidCommission,
+    ContractPaused,
+    PriceTooLow,
+    ListingExpired,
+    ContractNotAllowed,
+    PriceOverflow,
+    TransferFailed,
+    OfferNotFound,
+    OfferAlreadyExists,
+    /// No longer returned, kept so the error codes of the later variants do not change.
+    NotOperator,
+    TooFrequent,
+    ConfigLocked,
+    AlreadyListed,
+    UnsupportedStandard,
+    InvalidRoyalty,
+    MarketplaceNotOperator,
+    TooManyOffers,
 }
 
-type ContractError = Cis2Error<MarketplaceError>;
+/// Error returned by the entrypoints. It wraps `Cis2Error`, so the standard CIS2 errors keep their codes, and
+/// unlike `Cis2Error` it also returns the serialized custom error, so callers can tell e.g. which token was
+/// not found.
+#[derive(Debug, PartialEq, Eq)]
+struct ContractError(Cis2Error<MarketplaceError>);
+
 type ContractResult<A> = Result<A, ContractError>;
 
+/// The error code is taken from the variant tag of the serialized error, so the first variant maps to -1,
+/// the second to -2, etc.
+impl From<MarketplaceError> for Reject {
+    fn from(e: MarketplaceError) -> Self {
+        let return_value = to_bytes(&e);
+        let mut reject = Reject::new(-i32::from(return_value[0]) - 1).unwrap_or_default();
+        reject.return_value = Some(return_value);
+        reject
+    }
+}
+
+impl From<ContractError> for Reject {
+    fn from(e: ContractError) -> Self {
+        match e.0 {
+            Cis2Error::Custom(error) => error.into(),
+            error => error.into(),
+        }
+    }
+}
+
+impl From<ParseError> for MarketplaceError {
+    fn from(_e: ParseError) -> Self {
+        MarketplaceError::ParseParams
+    }
+}
+
 impl<T> From<CallContractError<T>> for MarketplaceError {
     fn from(_e: CallContractError<T>) -> Self {
         MarketplaceError::InvokeContractError
     }
 }
 
+impl From<LogError> for MarketplaceError {
+    fn from(e: LogError) -> Self {
+        match e {
+            LogError::Full => MarketplaceError::LogFull,
+            LogError::Malformed => MarketplaceError::LogMalformed,
+        }
+    }
+}
+
+impl From<TransferError> for MarketplaceError {
+    fn from(_e: TransferError) -> Self {
+        MarketplaceError::InvokeTransferError
+    }
+}
+
 impl From<MarketplaceError> for ContractError {
     fn from(c: MarketplaceError) -> Self {
-        Cis2Error::Custom(c)
+        ContractError(Cis2Error::Custom(c))
+    }
+}
+
+impl From<ParseError> for ContractError {
+    fn from(e: ParseError) -> Self {
+        ContractError(e.into())
+    }
+}
+
+impl<T> From<CallContractError<T>> for ContractError {
+    fn from(e: CallContractError<T>) -> Self {
+        ContractError(e.into())
+    }
+}
+
+impl From<LogError> for ContractError {
+    fn from(e: LogError) -> Self {
+        ContractError(e.into())
+    }
+}
+
+impl From<TransferError> for ContractError {
+    fn from(e: TransferError) -> Self {
+        ContractError(e.into())
     }
 }
 
 type TokenId = TokenIdU32;
-type TokenPrice = TokenAmountU32;
+type TokenPrice = TokenAmountU64;
+
+#[derive(Serialize, SchemaType, Clone, Copy, PartialEq, Eq, Debug)]
+struct TokenIdentifier {
+    contract: ContractAddress,
+    token_id: TokenId,
+}
+
+#[derive(Serialize, SchemaType, PartialEq, Debug)]
+enum MarketplaceEvent {
+    Listed {
+        contract: ContractAddress,
+        token_id: TokenId,
+        price: TokenPrice,
+        seller: AccountAddress,
+    },
+    PriceUpdated {
+        contract: ContractAddress,
+        token_id: TokenId,
+        price: TokenPrice,
+    },
+    Withdrawn {
+        contract: ContractAddress,
+        token_id: TokenId,
+    },
+    Purchased {
+        contract: ContractAddress,
+        token_id: TokenId,
+        price: TokenPrice,
+        seller: AccountAddress,
+        buyer: AccountAddress,
+    },
+    Gifted {
+        contract: ContractAddress,
+        token_id: TokenId,
+        to: AccountAddress,
+    },
+}
+
+/// Prices are denominated in micro CCD.
+fn price_to_amount(price: TokenPrice) -> Amount {
+    Amount::from_micro_ccd(price.0)
+}
+
+/// Commission is given in basis points, i.e. 10000 is the full price.
+const MAX_COMMISSION_BPS: u16 = 10000;
+
+fn commission_amount(amount: Amount, commission_bps: u16) -> Amount {
+    let commission = u128::from(amount.micro_ccd) * u128::from(commission_bps) / u128::from(MAX_COMMISSION_BPS);
+    Amount::from_micro_ccd(commission as u64)
+}
+
+/// Scales a price up or down by the given basis points, or `None` if the result does not fit a `TokenPrice`.
+fn scale_price(price: TokenPrice, bps: u16, down: bool) -> Option<TokenPrice> {
+    let price = u128::from(price.0);
+    let change = price * u128::from(bps) / u128::from(MAX_COMMISSION_BPS);
+    let scaled = if down {
+        price.saturating_sub(change)
+    } else {
+        price + change
+    };
+    u64::try_from(scaled).ok().map(TokenAmountU64)
+}
+
+/// Maximum number of sales kept in `recent_sales`, older ones roll off.
+const MAX_RECENT_SALES: usize = 20;
+
+/// Maximum number of open offers on a token, so that accepting or cancelling one stays cheap.
+const MAX_OFFERS_PER_TOKEN: usize = 20;
+
+#[derive(Serialize, SchemaType, Clone, PartialEq, Debug)]
+struct SaleRecord {
+    sale_id: u64,
+    contract: ContractAddress,
+    token_id: TokenId,
+    price: TokenPrice,
+    buyer: AccountAddress,
+    timestamp: Timestamp,
+}
+
+#[derive(Serialize, Clone, Copy)]
+struct Listing {
+    price: TokenPrice,
+    seller: AccountAddress,
+    expiry: Option<Timestamp>,
+}
+
+impl Listing {
+    fn is_expired(&self, now: Timestamp) -> bool {
+        match self.expiry {
+            Some(expiry) => now > expiry,
+            None => false,
+        }
+    }
+}
+
+/// Share of each sale of a collection paid to its creator, in basis points like the commission.
+#[derive(Serialize, SchemaType, Clone, Copy, PartialEq, Eq, Debug)]
+struct Royalty {
+    recipient: AccountAddress,
+    bps: u16,
+}
+
+/// CIS2 token that listing prices are paid in instead of CCD.
+#[derive(Serialize, SchemaType, Clone, PartialEq, Eq, Debug)]
+struct PaymentToken {
+    contract: ContractAddress,
+    token_id: TokenIdVec,
+}
+
+#[derive(Serialize, SchemaType, Clone, Copy, PartialEq, Eq, Debug)]
+struct Offer {
+    bidder: AccountAddress,
+    amount: TokenPrice,
+}
 
 #[derive(Serial, DeserialWithState, Deletable)]
 #[concordium(state_parameter = "S")]
 struct State<S> {
-    tokens_for_sale: StateMap<TokenId, TokenPrice, S>,
+    tokens_for_sale: StateMap<TokenIdentifier, Listing, S>,
+    recent_sales: Vec<SaleRecord>,
+    max_sale: Option<SaleRecord>,
+    buyers: StateSet<AccountAddress, S>,
+    commission_bps: u16,
+    paused: bool,
+    admin: AccountAddress,
+    min_price: TokenPrice,
+    last_sale_time: Option<Timestamp>,
+    allowed_contracts: StateSet<ContractAddress, S>,
+    /// Id given to the next recorded sale, starting from 1.
+    next_sale_id: u64,
+    /// Offers on tokens, the offered CCD is held by the contract until the offer is cancelled or accepted.
+    offers: StateMap<TokenIdentifier, Vec<Offer>, S>,
+    /// Expiry given to listings placed without one, zero means no expiry.
+    default_listing_ttl: Duration,
+    /// Number of entries in `tokens_for_sale`, kept up to date by `insert_listing` and `remove_listing`.
+    listings_count: u64,
+    /// Time a token must wait after a sale before it can be sold again.
+    token_purchase_cooldown: Duration,
+    /// Time of the last sale of each token. Entries are never removed, there is one per token ever sold.
+    last_sold: StateMap<TokenIdentifier, Timestamp, S>,
+    /// Once set, the configuration setters are rejected for good.
+    config_locked: bool,
+    royalties: StateMap<ContractAddress, Royalty, S>,
+    /// When set, `purchase` and `buy` are paid in this token instead of CCD. Offers are always made in CCD.
+    payment_token: Option<PaymentToken>,
 }
 
 impl<S: HasStateApi> State<S> {
-    fn empty(state_builder: &mut StateBuilder<S>) -> State<S> {
+    fn empty(state_builder: &mut StateBuilder<S>, admin: AccountAddress) -> State<S> {
         State {
             tokens_for_sale: state_builder.new_map(),
+            recent_sales: Vec::new(),
+            max_sale: None,
+            buyers: state_builder.new_set(),
+            commission_bps: 0,
+            paused: false,
+            admin,
+            min_price: 0.into(),
+            last_sale_time: None,
+            allowed_contracts: state_builder.new_set(),
+            next_sale_id: 1,
+            offers: state_builder.new_map(),
+            default_listing_ttl: Duration::from_millis(0),
+            listings_count: 0,
+            token_purchase_cooldown: Duration::from_millis(0),
+            last_sold: state_builder.new_map(),
+            config_locked: false,
+            royalties: state_builder.new_map(),
+            payment_token: None,
+        }
+    }
+
+    /// CCD to attach when buying a token listed for `price`, nothing when the price is paid in the payment token.
+    fn expected_payment(&self, price: TokenPrice) -> Amount {
+        match self.payment_token {
+            Some(_) => Amount::zero(),
+            None => price_to_amount(price),
+        }
+    }
+
+    fn insert_listing(&mut self, token: TokenIdentifier, listing: Listing) {
+        if self.tokens_for_sale.insert(token, listing).is_none() {
+            self.listings_count += 1;
+        }
+    }
+
+    fn remove_listing(&mut self, token: &TokenIdentifier) -> Option<Listing> {
+        let listing = self.tokens_for_sale.remove_and_get(token);
+        if listing.is_some() {
+            self.listings_count -= 1;
+        }
+        listing
+    }
+
+    fn record_sale(&mut self, sale: SaleRecord) {
+        let is_record = match &self.max_sale {
+            Some(max) => sale.price > max.price,
+            None => true,
+        };
+        if is_record {
+            self.max_sale = Some(sale.clone());
+        }
+
+        self.last_sale_time = Some(sale.timestamp);
+        self.next_sale_id += 1;
+
+        if self.recent_sales.len() >= MAX_RECENT_SALES {
+            self.recent_sales.remove(0);
+        }
+        self.recent_sales.push(sale);
+    }
+
+    fn take_offer(&mut self, token: &TokenIdentifier, bidder: &AccountAddress) -> Option<Offer> {
+        let (offer, is_empty) = {
+            let mut offers = self.offers.get_mut(token)?;
+            let index = offers.iter().position(|offer| offer.bidder == *bidder)?;
+            let offer = offers.remove(index);
+            (offer, offers.is_empty())
+        };
+        if is_empty {
+            self.offers.remove(token);
         }
+        Some(offer)
     }
 }
 
-#[init(contract = "MarketplaceBeatoken")]
+#[derive(SchemaType, Serialize)]
+struct InitParameter {
+    admin: AccountAddress,
+    commission_bps: u16,
+    min_price: TokenPrice,
+}
+
+#[init(contract = "MarketplaceBeatoken", parameter = "InitParameter")]
 fn marketplace_init<S: HasStateApi>(
-    _ctx: &impl HasInitContext,
+    ctx: &impl HasInitContext,
     state_builder: &mut StateBuilder<S>,
 ) -> ContractResult<State<S>> {
-    Ok(State::empty(state_builder))
+    let param: InitParameter = ctx.parameter_cursor().get()?;
+
+    ensure!(
+        param.commission_bps <= MAX_COMMISSION_BPS,
+        MarketplaceError::InvalidCommission.into()
+    );
+
+    let mut state = State::empty(state_builder, param.admin);
+    state.commission_bps = param.commission_bps;
+    state.min_price = param.min_price;
+    Ok(state)
 }
 
-#[derive(SchemaType, Serial, Deserial)]
-struct PlaceForSaleParameter {
-    token_id: TokenId,
-    price: TokenPrice,
+/// Configuration setters can only be called by the admin, and only until the configuration is locked.
+fn ensure_can_configure<S: HasStateApi>(ctx: &impl HasReceiveContext, state: &State<S>) -> ContractResult<()> {
+    ensure!(
+        ctx.sender().matches_account(&state.admin),
+        MarketplaceError::Unauthorized.into()
+    );
+    ensure!(!state.config_locked, MarketplaceError::ConfigLocked.into());
+    Ok(())
 }
 
 #[receive(
     contract = "MarketplaceBeatoken",
-    name = "place_for_sale",
-    parameter = "PlaceForSaleParameter",
+    name = "lock_config",
     mutable
 )]
-fn marketplace_place_for_sale<S: HasStateApi>(
+fn marketplace_lock_config<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<State<S>, StateApiType = S>,
 ) -> ContractResult<()> {
-    let param: PlaceForSaleParameter = ctx.parameter_cursor().get()?;
-
     let sender = ctx.sender();
-    let owner = ctx.owner();
-
+    let admin = host.state().admin;
     ensure!(
-        sender.matches_account(&owner),
+        sender.matches_account(&admin),
         MarketplaceError::Unauthorized.into()
     );
 
-    let state = host.state_mut();
-    state.tokens_for_sale.insert(param.token_id, param.price);
+    host.state_mut().config_locked = true;
     Ok(())
 }
 
-#[derive(Serial, SchemaType, Clone, PartialEq)]
-struct ViewState {
-    tokens: Vec<ViewStateToken>,
-}
-
-#[derive(Serial, SchemaType, Clone, PartialEq)]
-struct ViewStateToken {
-    id: TokenId,
-    price: TokenPrice,
+#[derive(SchemaType, Serialize)]
+struct SetCommissionParameter {
+    commission_bps: u16,
 }
 
 #[receive(
     contract = "MarketplaceBeatoken",
-    name = "view_list_for_sale",
-    return_value = "ViewState"
+    name = "set_commission",
+    parameter = "SetCommissionParameter",
+    mutable
 )]
-fn marketplace_view_list_for_sale<S: HasStateApi>(
-    _ctx: &impl HasReceiveContext,
-    host: &impl HasHost<State<S>, StateApiType = S>,
-) -> ContractResult<ViewState> {
-    let mut view_state = ViewState { tokens: Vec::new() };
+fn marketplace_set_commission<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: SetCommissionParameter = ctx.parameter_cursor().get()?;
 
-    for (id, amount) in host.state().tokens_for_sale.iter() {
-        view_state.tokens.push(ViewStateToken {
-            id: *id,
-            price: *amount,
-        });
-    }
+    ensure_can_configure(ctx, host.state())?;
 
-    Ok(view_state)
+    ensure!(
+        param.commission_bps <= MAX_COMMISSION_BPS,
+        MarketplaceError::InvalidCommission.into()
+    );
+
+    // Commission and royalty of a collection together may not exceed the price, like in set_royalty.
+    let fits_royalties = host
+        .state()
+        .royalties
+        .iter()
+        .all(|(_, royalty)| u32::from(royalty.bps) + u32::from(param.commission_bps) <= u32::from(MAX_COMMISSION_BPS));
+    ensure!(fits_royalties, MarketplaceError::InvalidCommission.into());
+
+    host.state_mut().commission_bps = param.commission_bps;
+    Ok(())
 }
 
 #[derive(SchemaType, Serialize)]
-struct GetListedParameter {
-    token_ids: Vec<TokenId>,
+struct SetPausedParameter {
+    paused: bool,
 }
 
+/// While paused, listing, repricing and purchases are rejected. Withdrawals and views stay available.
 #[receive(
     contract = "MarketplaceBeatoken",
-    name = "get_listed_for_sale",
-    parameter = "GetListedParameter",
-    return_value = "ViewState"
+    name = "set_paused",
+    parameter = "SetPausedParameter",
+    mutable
 )]
-fn marketplace_get_listed_for_sale<S: HasStateApi>(
+fn marketplace_set_paused<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
-    host: &impl HasHost<State<S>, StateApiType = S>,
-) -> ContractResult<ViewState> {
-    let param: GetListedParameter = ctx.parameter_cursor().get()?;
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: SetPausedParameter = ctx.parameter_cursor().get()?;
 
-    let mut view_state = ViewState { tokens: Vec::new() };
-    let state = host.state();
+    let sender = ctx.sender();
+    let admin = host.state().admin;
+    ensure!(
+        sender.matches_account(&admin),
+        MarketplaceError::Unauthorized.into()
+    );
 
-    for id in param.token_ids {
-        if let Some(amount) = state.tokens_for_sale.get(&id) {
-            view_state.tokens.push(ViewStateToken {
-                id, 
-                price: *amount,
-            });
-        }
-    }
+    host.state_mut().paused = param.paused;
+    Ok(())
+}
 
-    Ok(view_state)
+#[derive(SchemaType, Serialize)]
+struct SetMinPriceParameter {
+    min_price: TokenPrice,
 }
 
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "set_min_price",
+    parameter = "SetMinPriceParameter",
+    mutable
+)]
+fn marketplace_set_min_price<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: SetMinPriceParameter = ctx.parameter_cursor().get()?;
+
+    ensure_can_configure(ctx, host.state())?;
 
+    host.state_mut().min_price = param.min_price;
+    Ok(())
+}
 
 #[derive(SchemaType, Serialize)]
-struct WithdrawParameter {
-    token_id: TokenId,
+struct SetDefaultListingTtlParameter {
+    ttl: Duration,
 }
 
 #[receive(
     contract = "MarketplaceBeatoken",
-    name = "withdraw",
-    parameter = "WithdrawParameter",
+    name = "set_default_listing_ttl",
+    parameter = "SetDefaultListingTtlParameter",
     mutable
 )]
-fn marketplace_withdraw<S: HasStateApi>(
+fn marketplace_set_default_listing_ttl<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<State<S>, StateApiType = S>,
 ) -> ContractResult<()> {
-    let param: WithdrawParameter = ctx.parameter_cursor().get()?;
+    let param: SetDefaultListingTtlParameter = ctx.parameter_cursor().get()?;
 
-    let sender = ctx.sender();
-    let owner = ctx.owner();
-    ensure!(
-        sender.matches_account(&owner),
-        MarketplaceError::Unauthorized.into()
-    );
+    ensure_can_configure(ctx, host.state())?;
 
-    let state = host.state_mut();
+    host.state_mut().default_listing_ttl = param.ttl;
+    Ok(())
+}
 
-    ensure!(
-        state.tokens_for_sale.get(&param.token_id).is_some(),
-        MarketplaceError::TokenNotFound.into()
-    );
+#[derive(SchemaType, Serialize)]
+struct SetTokenPurchaseCooldownParameter {
+    cooldown: Duration,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "set_token_purchase_cooldown",
+    parameter = "SetTokenPurchaseCooldownParameter",
+    mutable
+)]
+fn marketplace_set_token_purchase_cooldown<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: SetTokenPurchaseCooldownParameter = ctx.parameter_cursor().get()?;
 
-    state.tokens_for_sale.remove(&param.token_id);
+    ensure_can_configure(ctx, host.state())?;
+
+    host.state_mut().token_purchase_cooldown = param.cooldown;
     Ok(())
 }
 
 #[derive(SchemaType, Serialize)]
-struct PurchaseParameter {
-    token_id: TokenId,
-    from: AccountAddress,
-    to: AccountAddress,
+struct SetRoyaltyParameter {
     contract: ContractAddress,
+    recipient: AccountAddress,
+    bps: u16,
 }
 
 #[receive(
     contract = "MarketplaceBeatoken",
-    name = "purchase",
-    parameter = "PurchaseParameter",
+    name = "set_royalty",
+    parameter = "SetRoyaltyParameter",
     mutable
 )]
-fn marketplace_purchase<S: HasStateApi>(
+fn marketplace_set_royalty<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<State<S>, StateApiType = S>,
 ) -> ContractResult<()> {
-    let purchase: PurchaseParameter = ctx.parameter_cursor().get()?;
+    let param: SetRoyaltyParameter = ctx.parameter_cursor().get()?;
+
+    ensure_can_configure(ctx, host.state())?;
 
-    let sender = ctx.sender();
-    let owner = ctx.owner();
     ensure!(
-        sender.matches_account(&owner),
-        MarketplaceError::Unauthorized.into()
+        u32::from(param.bps) + u32::from(host.state().commission_bps) <= u32::from(MAX_COMMISSION_BPS),
+        MarketplaceError::InvalidRoyalty.into()
     );
 
-    let state = host.state_mut();
-    let token = state.tokens_for_sale.remove_and_get(&purchase.token_id);
-    ensure!(token.is_some(), MarketplaceError::TokenNotFound.into());
-
-    let transfer = Transfer::<TokenId, TokenPrice> {
-        token_id: purchase.token_id,
-        amount: 1.into(),
-        from: Address::Account(purchase.from),
-        to: Receiver::Account(purchase.to),
-        data: AdditionalData::empty(),
-    };
+    host.state_mut().royalties.insert(param.contract, Royalty {
+        recipient: param.recipient,
+        bps: param.bps,
+    });
+    Ok(())
+}
 
-    let parameter = TransferParams::from(vec![transfer]);
+/// Sets the CIS2 token prices are paid in, or `None` to go back to CCD. Whoever calls `buy` or `purchase` must make
+/// the marketplace an operator on the payment token contract so it can transfer the price from them.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "set_payment_token",
+    parameter = "Option<PaymentToken>",
+    mutable
+)]
+fn marketplace_set_payment_token<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: Option<PaymentToken> = ctx.parameter_cursor().get()?;
 
-    host.invoke_contract(
-        &(purchase.contract),
-        &parameter,
-        EntrypointName::new_unchecked("transfer"),
-        Amount::zero(),
-    )?;
+    ensure_can_configure(ctx, host.state())?;
 
+    host.state_mut().payment_token = param;
     Ok(())
 }
 
-#[concordium_cfg_test]
-mod tests {
-    use super::*;
-    use concordium_std::test_infrastructure::*;
+#[derive(SchemaType, Serialize)]
+struct AllowedContractParameter {
+    contract: ContractAddress,
+}
 
-    const OWNER: AccountAddress = AccountAddress([0u8; 32]);
-    const OWNER_ADDR: Address = Address::Account(OWNER);
+/// Only tokens from allowed NFT contracts can be placed for sale.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "add_allowed_contract",
+    parameter = "AllowedContractParameter",
+    mutable
+)]
+fn marketplace_add_allowed_contract<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: AllowedContractParameter = ctx.parameter_cursor().get()?;
 
-    const RECEIVER: AccountAddress = AccountAddress([1u8; 32]);
+    ensure_can_configure(ctx, host.state())?;
+
+    host.state_mut().allowed_contracts.insert(param.contract);
+    Ok(())
+}
+
+/// Existing listings from a removed contract are kept, but no new ones can be placed.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "remove_allowed_contract",
+    parameter = "AllowedContractParameter",
+    mutable
+)]
+fn marketplace_remove_allowed_contract<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: AllowedContractParameter = ctx.parameter_cursor().get()?;
+
+    ensure_can_configure(ctx, host.state())?;
+
+    host.state_mut().allowed_contracts.remove(&param.contract);
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct TransferAdminParameter {
+    new_admin: AccountAddress,
+}
+
+/// Hands the marketplace over to a new admin, e.g. a multisig account.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "transfer_admin",
+    parameter = "TransferAdminParameter",
+    mutable
+)]
+fn marketplace_transfer_admin<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: TransferAdminParameter = ctx.parameter_cursor().get()?;
+
+    let sender = ctx.sender();
+    let admin = host.state().admin;
+    ensure!(
+        sender.matches_account(&admin),
+        MarketplaceError::Unauthorized.into()
+    );
+
+    host.state_mut().admin = param.new_admin;
+    Ok(())
+}
+
+fn balance_of<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    contract: &ContractAddress,
+    token_id: TokenId,
+    address: Address,
+) -> ContractResult<TokenPrice> {
+    let parameter = BalanceOfQueryParams {
+        queries: vec![BalanceOfQuery { token_id, address }],
+    };
+
+    let (_, response) = host.invoke_contract(
+        contract,
+        &parameter,
+        EntrypointName::new_unchecked("balanceOf"),
+        Amount::zero(),
+    )?;
+
+    let mut response = response.ok_or(MarketplaceError::InvokeContractError)?;
+    let response: BalanceOfQueryResponse<TokenPrice> = response.get()?;
+    let balance = response.0.first().ok_or(MarketplaceError::InvokeContractError)?;
+
+    Ok(*balance)
+}
+
+fn is_operator_of<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    contract: &ContractAddress,
+    owner: Address,
+    address: Address,
+) -> ContractResult<bool> {
+    let parameter = OperatorOfQueryParams {
+        queries: vec![OperatorOfQuery { owner, address }],
+    };
+
+    let (_, response) = host.invoke_contract(
+        contract,
+        &parameter,
+        EntrypointName::new_unchecked("operatorOf"),
+        Amount::zero(),
+    )?;
+
+    let mut response = response.ok_or(MarketplaceError::InvokeContractError)?;
+    let response: OperatorOfQueryResponse = response.get()?;
+    let is_operator = response.0.first().ok_or(MarketplaceError::InvokeContractError)?;
+
+    Ok(*is_operator)
+}
+
+/// Returns whether a listing of `seller` can still be bought, i.e. the seller owns the token and the
+/// marketplace is their operator. A failed query is returned as an error, it says nothing about the listing.
+fn is_fulfillable<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    contract: &ContractAddress,
+    token_id: TokenId,
+    seller: AccountAddress,
+) -> ContractResult<bool> {
+    let seller = Address::Account(seller);
+    if balance_of(host, contract, token_id, seller)? == 0.into() {
+        return Ok(false);
+    }
+    is_operator_of(host, contract, seller, Address::Contract(ctx.self_address()))
+}
+
+/// Rejects unless the marketplace is an operator of `owner` on the NFT contract, which it needs to be to
+/// transfer the tokens of `owner`.
+fn ensure_marketplace_operator<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    contract: &ContractAddress,
+    owner: Address,
+) -> ContractResult<()> {
+    let marketplace = Address::Contract(ctx.self_address());
+    let is_operator = is_operator_of(host, contract, owner, marketplace)?;
+    ensure!(is_operator, MarketplaceError::MarketplaceNotOperator.into());
+    Ok(())
+}
+
+#[derive(SchemaType, Serial, Deserial)]
+struct PlaceForSaleParameter {
+    token_id: TokenId,
+    price: TokenPrice,
+    contract: ContractAddress,
+    expiry: Option<Timestamp>,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "place_for_sale",
+    parameter = "PlaceForSaleParameter",
+    mutable,
+    enable_logger
+)]
+fn marketplace_place_for_sale<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: PlaceForSaleParameter = ctx.parameter_cursor().get()?;
+
+    let seller = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    place_listing(ctx, host, seller, &param, logger)
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "place_for_sale_batch",
+    parameter = "Vec<PlaceForSaleParameter>",
+    mutable,
+    enable_logger
+)]
+fn marketplace_place_for_sale_batch<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: Vec<PlaceForSaleParameter> = ctx.parameter_cursor().get()?;
+
+    let seller = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    // A failing entry rejects the whole transaction, so no partial listings are kept.
+    for param in params.iter() {
+        place_listing(ctx, host, seller, param, logger)?;
+    }
+
+    Ok(())
+}
+
+fn place_listing<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    seller: AccountAddress,
+    param: &PlaceForSaleParameter,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+    ensure!(
+        param.price >= host.state().min_price,
+        MarketplaceError::PriceTooLow.into()
+    );
+    ensure!(
+        host.state().allowed_contracts.contains(&param.contract),
+        MarketplaceError::ContractNotAllowed.into()
+    );
+    if let Some(expiry) = param.expiry {
+        ensure!(
+            expiry > ctx.metadata().slot_time(),
+            MarketplaceError::ListingExpired.into()
+        );
+    }
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    // Prices of listed tokens are changed with update_price.
+    ensure!(
+        host.state().tokens_for_sale.get(&token).is_none(),
+        MarketplaceError::AlreadyListed.into()
+    );
+
+    ensure!(
+        supports_cis2(host, &param.contract)?,
+        MarketplaceError::UnsupportedStandard.into()
+    );
+
+    let balance = balance_of(host, &param.contract, param.token_id, Address::Account(seller))?;
+    ensure!(
+        balance != 0.into(),
+        MarketplaceError::NotTokenOwner.into()
+    );
+
+    let state = host.state_mut();
+
+    let ttl = state.default_listing_ttl;
+    let expiry = match param.expiry {
+        Some(expiry) => Some(expiry),
+        None if ttl.millis() > 0 => ctx.metadata().slot_time().checked_add(ttl),
+        None => None,
+    };
+
+    state.insert_listing(token, Listing {
+        price: param.price,
+        seller,
+        expiry,
+    });
+
+    logger.log(&MarketplaceEvent::Listed {
+        contract: param.contract,
+        token_id: param.token_id,
+        price: param.price,
+        seller,
+    })?;
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct UpdatePriceParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+    new_price: TokenPrice,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "update_price",
+    parameter = "UpdatePriceParameter",
+    mutable,
+    enable_logger
+)]
+fn marketplace_update_price<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: UpdatePriceParameter = ctx.parameter_cursor().get()?;
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+    ensure!(
+        param.new_price >= host.state().min_price,
+        MarketplaceError::PriceTooLow.into()
+    );
+
+    let sender = ctx.sender();
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let state = host.state_mut();
+    let mut listing = state
+        .tokens_for_sale
+        .get_mut(&token)
+        .ok_or(MarketplaceError::TokenNotFound(token.token_id))?;
+
+    ensure!(
+        sender.matches_account(&listing.seller),
+        MarketplaceError::Unauthorized.into()
+    );
+
+    listing.price = param.new_price;
+
+    logger.log(&MarketplaceEvent::PriceUpdated {
+        contract: param.contract,
+        token_id: param.token_id,
+        price: param.new_price,
+    })?;
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct RepriceAllParameter {
+    markup_bps: u16,
+    down: bool,
+}
+
+/// Scales every listing of the sender by `markup_bps`. If any new price is out of bounds, nothing is repriced.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "reprice_all_by_seller",
+    parameter = "RepriceAllParameter",
+    mutable,
+    enable_logger
+)]
+fn marketplace_reprice_all_by_seller<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: RepriceAllParameter = ctx.parameter_cursor().get()?;
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+
+    let sender = ctx.sender();
+    let state = host.state_mut();
+    let min_price = state.min_price;
+
+    let mut repriced = Vec::new();
+    for (token, listing) in state.tokens_for_sale.iter() {
+        if !sender.matches_account(&listing.seller) {
+            continue;
+        }
+
+        let price = scale_price(listing.price, param.markup_bps, param.down).ok_or(MarketplaceError::PriceOverflow)?;
+        ensure!(price >= min_price, MarketplaceError::PriceTooLow.into());
+
+        repriced.push((*token, price));
+    }
+
+    for (token, price) in repriced {
+        if let Some(mut listing) = state.tokens_for_sale.get_mut(&token) {
+            listing.price = price;
+        }
+
+        logger.log(&MarketplaceEvent::PriceUpdated {
+            contract: token.contract,
+            token_id: token.token_id,
+            price,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[derive(Serial, SchemaType, Clone, PartialEq)]
+struct ViewState {
+    tokens: Vec<ViewStateToken>,
+}
+
+#[derive(Serial, SchemaType, Clone, PartialEq)]
+struct ViewStateToken {
+    contract: ContractAddress,
+    id: TokenId,
+    price: TokenPrice,
+    seller: AccountAddress,
+    expired: bool,
+}
+
+impl ViewStateToken {
+    fn new(token: &TokenIdentifier, listing: &Listing, now: Timestamp) -> Self {
+        ViewStateToken {
+            contract: token.contract,
+            id: token.token_id,
+            price: listing.price,
+            seller: listing.seller,
+            expired: listing.is_expired(now),
+        }
+    }
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_list_for_sale",
+    return_value = "ViewState"
+)]
+fn marketplace_view_list_for_sale<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ViewState> {
+    let now = ctx.metadata().slot_time();
+
+    let mut view_state = ViewState { tokens: Vec::new() };
+
+    for (token, listing) in host.state().tokens_for_sale.iter() {
+        view_state.tokens.push(ViewStateToken::new(&token, &listing, now));
+    }
+
+    Ok(view_state)
+}
+
+/// The configuration of the marketplace, the allowed contracts and royalties are only counted.
+#[derive(Serialize, SchemaType, PartialEq, Eq, Debug)]
+struct ConfigView {
+    admin: AccountAddress,
+    commission_bps: u16,
+    min_price: TokenPrice,
+    paused: bool,
+    config_locked: bool,
+    default_listing_ttl: Duration,
+    token_purchase_cooldown: Duration,
+    payment_token: Option<PaymentToken>,
+    allowed_contracts_count: u32,
+    royalties_count: u32,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_config",
+    return_value = "ConfigView"
+)]
+fn marketplace_view_config<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ConfigView> {
+    let state = host.state();
+    Ok(ConfigView {
+        admin: state.admin,
+        commission_bps: state.commission_bps,
+        min_price: state.min_price,
+        paused: state.paused,
+        config_locked: state.config_locked,
+        default_listing_ttl: state.default_listing_ttl,
+        token_purchase_cooldown: state.token_purchase_cooldown,
+        payment_token: state.payment_token.clone(),
+        allowed_contracts_count: state.allowed_contracts.iter().count() as u32,
+        royalties_count: state.royalties.iter().count() as u32,
+    })
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_listings_count",
+    return_value = "u64"
+)]
+fn marketplace_view_listings_count<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    Ok(host.state().listings_count)
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewListingParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_listing",
+    parameter = "ViewListingParameter",
+    return_value = "Option<ViewStateToken>"
+)]
+fn marketplace_view_listing<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Option<ViewStateToken>> {
+    let param: ViewListingParameter = ctx.parameter_cursor().get()?;
+
+    let now = ctx.metadata().slot_time();
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let listing = host.state().tokens_for_sale.get(&token);
+
+    Ok(listing.map(|listing| ViewStateToken::new(&token, &listing, now)))
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewPagedParameter {
+    skip: u32,
+    take: u32,
+}
+
+#[derive(Serial, SchemaType, Clone, PartialEq)]
+struct ViewPagedState {
+    tokens: Vec<ViewStateToken>,
+    has_more: bool,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_list_for_sale_paged",
+    parameter = "ViewPagedParameter",
+    return_value = "ViewPagedState"
+)]
+fn marketplace_view_list_for_sale_paged<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ViewPagedState> {
+    let param: ViewPagedParameter = ctx.parameter_cursor().get()?;
+
+    let now = ctx.metadata().slot_time();
+
+    let mut view_state = ViewPagedState {
+        tokens: Vec::new(),
+        has_more: false,
+    };
+
+    let mut listings = host.state().tokens_for_sale.iter().skip(param.skip as usize);
+
+    for (token, listing) in listings.by_ref().take(param.take as usize) {
+        view_state.tokens.push(ViewStateToken::new(&token, &listing, now));
+    }
+    view_state.has_more = listings.next().is_some();
+
+    Ok(view_state)
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_compact",
+    return_value = "Vec<(TokenIdentifier, TokenPrice)>"
+)]
+fn marketplace_view_compact<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<(TokenIdentifier, TokenPrice)>> {
+    let tokens = host
+        .state()
+        .tokens_for_sale
+        .iter()
+        .map(|(token, listing)| (*token, listing.price))
+        .collect();
+
+    Ok(tokens)
+}
+
+#[derive(SchemaType, Serialize)]
+struct GetListedParameter {
+    tokens: Vec<TokenIdentifier>,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "get_listed_for_sale",
+    parameter = "GetListedParameter",
+    return_value = "ViewState"
+)]
+fn marketplace_get_listed_for_sale<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ViewState> {
+    let param: GetListedParameter = ctx.parameter_cursor().get()?;
+
+    let now = ctx.metadata().slot_time();
+
+    let mut view_state = ViewState { tokens: Vec::new() };
+    let state = host.state();
+
+    for token in param.tokens {
+        if let Some(listing) = state.tokens_for_sale.get(&token) {
+            view_state.tokens.push(ViewStateToken::new(&token, &listing, now));
+        }
+    }
+
+    Ok(view_state)
+}
+
+
+
+#[derive(SchemaType, Serialize)]
+struct ViewAffordableParameter {
+    budget: TokenPrice,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_affordable",
+    parameter = "ViewAffordableParameter",
+    return_value = "ViewState"
+)]
+fn marketplace_view_affordable<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ViewState> {
+    let param: ViewAffordableParameter = ctx.parameter_cursor().get()?;
+
+    let now = ctx.metadata().slot_time();
+
+    let mut view_state = ViewState { tokens: Vec::new() };
+
+    for (token, listing) in host.state().tokens_for_sale.iter() {
+        if listing.price <= param.budget {
+            view_state.tokens.push(ViewStateToken::new(&token, &listing, now));
+        }
+    }
+
+    Ok(view_state)
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_listings_by_seller",
+    parameter = "AccountAddress",
+    return_value = "ViewState"
+)]
+fn marketplace_view_listings_by_seller<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ViewState> {
+    let seller: AccountAddress = ctx.parameter_cursor().get()?;
+
+    let now = ctx.metadata().slot_time();
+
+    let mut view_state = ViewState { tokens: Vec::new() };
+
+    for (token, listing) in host.state().tokens_for_sale.iter() {
+        if listing.seller == seller {
+            view_state.tokens.push(ViewStateToken::new(&token, &listing, now));
+        }
+    }
+
+    Ok(view_state)
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewPriceHistogramParameter {
+    boundaries: Vec<TokenPrice>,
+}
+
+/// Bucket `i` counts prices from `boundaries[i - 1]` (inclusive) up to `boundaries[i]` (exclusive),
+/// so the result has one more bucket than there are boundaries.
+fn price_histogram(boundaries: &[TokenPrice], prices: impl Iterator<Item = TokenPrice>) -> Vec<u32> {
+    let mut counts = vec![0; boundaries.len() + 1];
+    for price in prices {
+        let bucket = boundaries.iter().filter(|boundary| price >= **boundary).count();
+        counts[bucket] += 1;
+    }
+    counts
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_price_histogram",
+    parameter = "ViewPriceHistogramParameter",
+    return_value = "Vec<u32>"
+)]
+fn marketplace_view_price_histogram<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<u32>> {
+    let param: ViewPriceHistogramParameter = ctx.parameter_cursor().get()?;
+
+    let prices = host.state().tokens_for_sale.iter().map(|(_, listing)| listing.price);
+
+    Ok(price_histogram(&param.boundaries, prices))
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewContractPriceHistogramParameter {
+    contract: ContractAddress,
+    boundaries: Vec<TokenPrice>,
+}
+
+/// Same as `view_price_histogram`, restricted to the listings of one NFT contract.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_contract_price_histogram",
+    parameter = "ViewContractPriceHistogramParameter",
+    return_value = "Vec<u32>"
+)]
+fn marketplace_view_contract_price_histogram<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<u32>> {
+    let param: ViewContractPriceHistogramParameter = ctx.parameter_cursor().get()?;
+
+    let prices = host
+        .state()
+        .tokens_for_sale
+        .iter()
+        .filter(|(token, _)| token.contract == param.contract)
+        .map(|(_, listing)| listing.price);
+
+    Ok(price_histogram(&param.boundaries, prices))
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_now",
+    return_value = "Timestamp"
+)]
+fn marketplace_view_now<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    _host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Timestamp> {
+    Ok(ctx.metadata().slot_time())
+}
+
+/// Milliseconds elapsed since the last sale, or `None` if nothing has been sold yet.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_time_since_last_sale",
+    return_value = "Option<u64>"
+)]
+fn marketplace_view_time_since_last_sale<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Option<u64>> {
+    let now = ctx.metadata().slot_time();
+    let elapsed = host
+        .state()
+        .last_sale_time
+        .map(|last| now.timestamp_millis().saturating_sub(last.timestamp_millis()));
+    Ok(elapsed)
+}
+
+fn supports_cis2<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    contract: &ContractAddress,
+) -> ContractResult<bool> {
+    let parameter = SupportsQueryParams {
+        queries: vec![CIS2_STANDARD_IDENTIFIER.to_owned()],
+    };
+
+    let result = host.invoke_contract(
+        contract,
+        &parameter,
+        EntrypointName::new_unchecked("supports"),
+        Amount::zero(),
+    );
+
+    // A contract without a supports entrypoint, or rejecting the query, does not implement CIS2.
+    let response = match result {
+        Ok((_, response)) => response,
+        Err(CallContractError::MissingEntrypoint) | Err(CallContractError::LogicReject { .. }) => return Ok(false),
+        Err(e) => bail!(e.into()),
+    };
+
+    let mut response = response.ok_or(MarketplaceError::InvokeContractError)?;
+    let response: SupportsQueryResponse = response.get()?;
+
+    Ok(matches!(response.results.first(), Some(SupportResult::Support)))
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewBrokenListingsParameter {
+    contract: ContractAddress,
+    token_ids: Vec<TokenId>,
+}
+
+/// Returns the given listings that can no longer be bought, see refresh_and_prune.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_broken_listings",
+    parameter = "ViewBrokenListingsParameter",
+    return_value = "Vec<TokenId>",
+    mutable
+)]
+fn marketplace_view_broken_listings<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<TokenId>> {
+    let param: ViewBrokenListingsParameter = ctx.parameter_cursor().get()?;
+
+    let mut broken = Vec::new();
+
+    for id in param.token_ids {
+        let token = TokenIdentifier {
+            contract: param.contract,
+            token_id: id,
+        };
+        let seller = match host.state().tokens_for_sale.get(&token) {
+            Some(listing) => listing.seller,
+            None => continue,
+        };
+
+        // Like in refresh_and_prune, a listing whose queries fail is not reported.
+        if let Ok(false) = is_fulfillable(ctx, host, &param.contract, id, seller) {
+            broken.push(id);
+        }
+    }
+
+    Ok(broken)
+}
+
+#[derive(SchemaType, Serialize)]
+struct RefreshAndPruneParameter {
+    contract: ContractAddress,
+    token_ids: Vec<TokenId>,
+}
+
+/// Removes the given listings whose seller no longer owns the token or no longer has the marketplace as
+/// operator, and returns how many were removed. Can be called by anyone on purpose: such a listing cannot be
+/// bought, so removing it takes nothing from the seller, who can list the token again after approving the
+/// marketplace.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "refresh_and_prune",
+    parameter = "RefreshAndPruneParameter",
+    return_value = "u32",
+    mutable,
+    enable_logger
+)]
+fn marketplace_refresh_and_prune<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<u32> {
+    let param: RefreshAndPruneParameter = ctx.parameter_cursor().get()?;
+
+    let mut pruned = 0;
+
+    for id in param.token_ids {
+        let token = TokenIdentifier {
+            contract: param.contract,
+            token_id: id,
+        };
+        let seller = match host.state().tokens_for_sale.get(&token) {
+            Some(listing) => listing.seller,
+            None => continue,
+        };
+
+        // A failed query says nothing about the listing, e.g. the NFT contract may be paused, so it is kept.
+        if let Ok(false) = is_fulfillable(ctx, host, &param.contract, id, seller) {
+            host.state_mut().remove_listing(&token);
+            logger.log(&MarketplaceEvent::Withdrawn {
+                contract: param.contract,
+                token_id: id,
+            })?;
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+#[derive(SchemaType, Serialize)]
+struct PruneListingParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+}
+
+/// Removes a listing whose seller no longer owns the token, e.g. because it was transferred away without
+/// withdrawing it. Can be called by anyone, returns whether the listing was removed.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "prune_listing",
+    parameter = "PruneListingParameter",
+    return_value = "bool",
+    mutable,
+    enable_logger
+)]
+fn marketplace_prune_listing<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<bool> {
+    let param: PruneListingParameter = ctx.parameter_cursor().get()?;
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let seller = match host.state().tokens_for_sale.get(&token) {
+        Some(listing) => Address::Account(listing.seller),
+        None => bail!(MarketplaceError::TokenNotFound(token.token_id).into()),
+    };
+
+    let balance = balance_of(host, &param.contract, param.token_id, seller)?;
+    if balance != 0.into() {
+        return Ok(false);
+    }
+
+    host.state_mut().remove_listing(&token);
+    logger.log(&MarketplaceEvent::Withdrawn {
+        contract: param.contract,
+        token_id: param.token_id,
+    })?;
+
+    Ok(true)
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewSellerReadinessParameter {
+    contract: ContractAddress,
+    token_id: TokenId,
+    seller: AccountAddress,
+}
+
+#[derive(SchemaType, Serialize, PartialEq, Debug)]
+struct SellerReadiness {
+    is_operator: bool,
+    balance: TokenPrice,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_seller_readiness",
+    parameter = "ViewSellerReadinessParameter",
+    return_value = "SellerReadiness",
+    mutable
+)]
+fn marketplace_view_seller_readiness<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<SellerReadiness> {
+    let param: ViewSellerReadinessParameter = ctx.parameter_cursor().get()?;
+
+    let seller = Address::Account(param.seller);
+    let marketplace = Address::Contract(ctx.self_address());
+
+    let is_operator = is_operator_of(host, &param.contract, seller, marketplace)?;
+    let balance = balance_of(host, &param.contract, param.token_id, seller)?;
+
+    Ok(SellerReadiness { is_operator, balance })
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewContractCompatibleParameter {
+    contract: ContractAddress,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_contract_compatible",
+    parameter = "ViewContractCompatibleParameter",
+    return_value = "bool",
+    mutable
+)]
+fn marketplace_view_contract_compatible<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<bool> {
+    let param: ViewContractCompatibleParameter = ctx.parameter_cursor().get()?;
+
+    supports_cis2(host, &param.contract)
+}
+
+#[derive(SchemaType, Serialize)]
+struct WithdrawParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "withdraw",
+    parameter = "WithdrawParameter",
+    mutable,
+    enable_logger
+)]
+fn marketplace_withdraw<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: WithdrawParameter = ctx.parameter_cursor().get()?;
+
+    let sender = ctx.sender();
+    let admin = host.state().admin;
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let state = host.state_mut();
+
+    let seller = match state.tokens_for_sale.get(&token) {
+        Some(listing) => listing.seller,
+        None => bail!(MarketplaceError::TokenNotFound(token.token_id).into()),
+    };
+
+    ensure!(
+        sender.matches_account(&seller) || sender.matches_account(&admin),
+        MarketplaceError::Unauthorized.into()
+    );
+
+    state.remove_listing(&token);
+
+    logger.log(&MarketplaceEvent::Withdrawn {
+        contract: param.contract,
+        token_id: param.token_id,
+    })?;
+
+    Ok(())
+}
+
+/// Withdraws every listing of the sender. Succeeds without changes if the sender has no listings.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "withdraw_all",
+    mutable,
+    enable_logger
+)]
+fn marketplace_withdraw_all<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let sender = ctx.sender();
+    let state = host.state_mut();
+
+    let tokens: Vec<TokenIdentifier> = state
+        .tokens_for_sale
+        .iter()
+        .filter(|(_, listing)| sender.matches_account(&listing.seller))
+        .map(|(token, _)| *token)
+        .collect();
+
+    for token in tokens {
+        state.remove_listing(&token);
+
+        logger.log(&MarketplaceEvent::Withdrawn {
+            contract: token.contract,
+            token_id: token.token_id,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct TokenTransferredParameter {
+    token_id: TokenId,
+    owner: AccountAddress,
+    balance: TokenPrice,
+}
+
+/// Called by an NFT contract after a transfer, with the remaining balance of the previous owner.
+/// A listing whose seller no longer holds the token is removed.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "on_token_transferred",
+    parameter = "TokenTransferredParameter",
+    mutable,
+    enable_logger
+)]
+fn marketplace_on_token_transferred<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: TokenTransferredParameter = ctx.parameter_cursor().get()?;
+
+    let contract = match ctx.sender() {
+        Address::Contract(contract) => contract,
+        Address::Account(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    let token = TokenIdentifier {
+        contract,
+        token_id: param.token_id,
+    };
+
+    let state = host.state_mut();
+
+    let is_stale = match state.tokens_for_sale.get(&token) {
+        Some(listing) => listing.seller == param.owner && param.balance == 0.into(),
+        None => false,
+    };
+
+    if is_stale {
+        state.remove_listing(&token);
+
+        logger.log(&MarketplaceEvent::Withdrawn {
+            contract,
+            token_id: param.token_id,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct GiftParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+    to: AccountAddress,
+}
+
+/// Transfers listed tokens from their sellers to the given recipients for free and removes the listings.
+/// If any gift is unauthorized or its transfer fails, the whole batch is rejected.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "gift_batch",
+    parameter = "Vec<GiftParameter>",
+    mutable,
+    enable_logger
+)]
+fn marketplace_gift_batch<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let gifts: Vec<GiftParameter> = ctx.parameter_cursor().get()?;
+
+    let sender = ctx.sender();
+
+    for gift in gifts.iter() {
+        let token = TokenIdentifier {
+            contract: gift.contract,
+            token_id: gift.token_id,
+        };
+
+        let listing = host.state().tokens_for_sale.get(&token).map(|listing| *listing);
+        let listing = listing.ok_or(MarketplaceError::TokenNotFound(token.token_id))?;
+
+        ensure!(
+            sender.matches_account(&listing.seller),
+            MarketplaceError::Unauthorized.into()
+        );
+
+        ensure_marketplace_operator(ctx, host, &gift.contract, Address::Account(listing.seller))?;
+
+        host.state_mut().remove_listing(&token);
+
+        let transfer = Transfer::<TokenId, TokenPrice> {
+            token_id: gift.token_id,
+            amount: 1.into(),
+            from: Address::Account(listing.seller),
+            to: Receiver::Account(gift.to),
+            data: AdditionalData::empty(),
+        };
+
+        let parameter = TransferParams::from(vec![transfer]);
+
+        host.invoke_contract(
+            &(gift.contract),
+            &parameter,
+            EntrypointName::new_unchecked("transfer"),
+            Amount::zero(),
+        )
+        .map_err(|_| MarketplaceError::TransferFailed)?;
+
+        logger.log(&MarketplaceEvent::Gifted {
+            contract: gift.contract,
+            token_id: gift.token_id,
+            to: gift.to,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct PurchaseParameter {
+    token_id: TokenId,
+    to: AccountAddress,
+    contract: ContractAddress,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "purchase",
+    parameter = "PurchaseParameter",
+    mutable,
+    payable,
+    enable_logger
+)]
+fn marketplace_purchase<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    amount: Amount,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let purchase: PurchaseParameter = ctx.parameter_cursor().get()?;
+
+    let sender = ctx.sender();
+    let admin = host.state().admin;
+    ensure!(
+        sender.matches_account(&admin),
+        MarketplaceError::Unauthorized.into()
+    );
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+
+    execute_purchase(ctx, host, &purchase, amount, logger)
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "purchase_batch",
+    parameter = "Vec<PurchaseParameter>",
+    mutable,
+    payable,
+    enable_logger
+)]
+fn marketplace_purchase_batch<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    amount: Amount,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let purchases: Vec<PurchaseParameter> = ctx.parameter_cursor().get()?;
+
+    let sender = ctx.sender();
+    let admin = host.state().admin;
+    ensure!(
+        sender.matches_account(&admin),
+        MarketplaceError::Unauthorized.into()
+    );
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+
+    let mut payments = Vec::with_capacity(purchases.len());
+    for purchase in purchases.iter() {
+        let token = TokenIdentifier {
+            contract: purchase.contract,
+            token_id: purchase.token_id,
+        };
+        let listing = host.state().tokens_for_sale.get(&token).map(|listing| *listing);
+        let listing = listing.ok_or(MarketplaceError::TokenNotFound(token.token_id))?;
+        payments.push(host.state().expected_payment(listing.price));
+    }
+
+    let total: u128 = payments.iter().map(|payment| u128::from(payment.micro_ccd)).sum();
+    ensure!(
+        total == u128::from(amount.micro_ccd),
+        MarketplaceError::InsufficientPayment.into()
+    );
+
+    // A failing item rejects the whole transaction, so the buyer is never charged for part of the batch.
+    for (purchase, payment) in purchases.iter().zip(payments) {
+        execute_purchase(ctx, host, purchase, payment, logger)?;
+    }
+
+    Ok(())
+}
+
+/// Sends a listed token to the buyer and pays out the listing price, `amount` must be the payment expected for it.
+fn execute_purchase<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    purchase: &PurchaseParameter,
+    amount: Amount,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let token = TokenIdentifier {
+        contract: purchase.contract,
+        token_id: purchase.token_id,
+    };
+
+    let listing = take_listing(ctx, host, &token, amount)?;
+
+    let transfer = Transfer::<TokenId, TokenPrice> {
+        token_id: purchase.token_id,
+        amount: 1.into(),
+        from: Address::Account(listing.seller),
+        to: Receiver::Account(purchase.to),
+        data: AdditionalData::empty(),
+    };
+
+    let parameter = TransferParams::from(vec![transfer]);
+
+    host.invoke_contract(
+        &(purchase.contract),
+        &parameter,
+        EntrypointName::new_unchecked("transfer"),
+        Amount::zero(),
+    )
+    .map_err(|_| MarketplaceError::TransferFailed)?;
+
+    let payment_token = host.state().payment_token.clone();
+    settle_sale(ctx, host, &token, listing.price, listing.seller, purchase.to, payment_token, logger)
+}
+
+/// Checks that the listed token can be bought for `amount` and removes its listing. The listing is removed before
+/// the token is transferred, so the NFT contract cannot re-enter and buy the token again during the transfer.
+fn take_listing<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    token: &TokenIdentifier,
+    amount: Amount,
+) -> ContractResult<Listing> {
+    let listing = host.state().tokens_for_sale.get(token).map(|listing| *listing);
+    let listing = listing.ok_or(MarketplaceError::TokenNotFound(token.token_id))?;
+
+    ensure!(
+        !listing.is_expired(ctx.metadata().slot_time()),
+        MarketplaceError::ListingExpired.into()
+    );
+
+    ensure!(
+        amount == host.state().expected_payment(listing.price),
+        MarketplaceError::InsufficientPayment.into()
+    );
+
+    ensure_marketplace_operator(ctx, host, &token.contract, Address::Account(listing.seller))?;
+    ensure_cooled_down(host.state(), token, ctx.metadata().slot_time())?;
+
+    host.state_mut().remove_listing(token);
+    Ok(listing)
+}
+
+/// Rejects selling `token` again before `token_purchase_cooldown` has passed since its last sale, whichever
+/// entrypoint it is sold through.
+fn ensure_cooled_down<S: HasStateApi>(state: &State<S>, token: &TokenIdentifier, now: Timestamp) -> ContractResult<()> {
+    let last_sold = state.last_sold.get(token).map(|last_sold| *last_sold);
+    if let Some(last_sold) = last_sold {
+        let cooled_down = match last_sold.checked_add(state.token_purchase_cooldown) {
+            Some(cooldown_end) => now >= cooldown_end,
+            None => false,
+        };
+        ensure!(cooled_down, MarketplaceError::TooFrequent.into());
+    }
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct BuyParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+}
+
+/// Lets any account buy a listed token by attaching exactly its price. The token is transferred from the seller
+/// by the marketplace, so the marketplace must be a CIS2 operator of the seller on the NFT contract.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "buy",
+    parameter = "BuyParameter",
+    mutable,
+    payable,
+    enable_logger
+)]
+fn marketplace_buy<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    amount: Amount,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: BuyParameter = ctx.parameter_cursor().get()?;
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+
+    let buyer = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let listing = take_listing(ctx, host, &token, amount)?;
+
+    let transfer = Transfer::<TokenId, TokenPrice> {
+        token_id: param.token_id,
+        amount: 1.into(),
+        from: Address::Account(listing.seller),
+        to: Receiver::Account(buyer),
+        data: AdditionalData::empty(),
+    };
+
+    let parameter = TransferParams::from(vec![transfer]);
+
+    host.invoke_contract(
+        &(param.contract),
+        &parameter,
+        EntrypointName::new_unchecked("transfer"),
+        Amount::zero(),
+    )
+    .map_err(|_| MarketplaceError::TransferFailed)?;
+
+    let payment_token = host.state().payment_token.clone();
+    settle_sale(ctx, host, &token, listing.price, listing.seller, buyer, payment_token, logger)
+}
+
+/// Forwards the payment held by the contract to the seller minus the commission, which goes to the admin,
+/// and the royalty of the collection, which goes to its creator. Then records the sale.
+/// With a `payment_token` the shares are instead transferred on the payment token contract from the sender, who
+/// signed the transaction. For `purchase` that is the admin, never the recipient of the token.
+#[allow(clippy::too_many_arguments)]
+fn settle_sale<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    token: &TokenIdentifier,
+    price: TokenPrice,
+    seller: AccountAddress,
+    buyer: AccountAddress,
+    payment_token: Option<PaymentToken>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let amount = price_to_amount(price);
+    let admin = host.state().admin;
+
+    let commission = commission_amount(amount, host.state().commission_bps);
+
+    let royalty = host.state().royalties.get(&token.contract).map(|royalty| *royalty);
+    let royalty_amount = match royalty {
+        Some(royalty) => commission_amount(amount, royalty.bps),
+        None => Amount::zero(),
+    };
+
+    let mut payouts = vec![(seller, amount - commission - royalty_amount)];
+    if commission > Amount::zero() {
+        payouts.push((admin, commission));
+    }
+    if let Some(royalty) = royalty {
+        if royalty_amount > Amount::zero() {
+            payouts.push((royalty.recipient, royalty_amount));
+        }
+    }
+
+    match payment_token {
+        None => {
+            for (to, amount) in payouts {
+                host.invoke_transfer(&to, amount)?;
+            }
+        }
+        Some(payment_token) => {
+            let transfers: Vec<_> = payouts
+                .into_iter()
+                .map(|(to, amount)| Transfer::<TokenIdVec, TokenPrice> {
+                    token_id: payment_token.token_id.clone(),
+                    amount: TokenAmountU64(amount.micro_ccd),
+                    from: ctx.sender(),
+                    to: Receiver::Account(to),
+                    data: AdditionalData::empty(),
+                })
+                .collect();
+            let parameter = TransferParams::from(transfers);
+
+            host.invoke_contract(
+                &payment_token.contract,
+                &parameter,
+                EntrypointName::new_unchecked("transfer"),
+                Amount::zero(),
+            )?;
+        }
+    }
+
+    let state = host.state_mut();
+    state.record_sale(SaleRecord {
+        sale_id: state.next_sale_id,
+        contract: token.contract,
+        token_id: token.token_id,
+        price,
+        buyer,
+        timestamp: ctx.metadata().slot_time(),
+    });
+    state.buyers.insert(buyer);
+    state.last_sold.insert(*token, ctx.metadata().slot_time());
+
+    logger.log(&MarketplaceEvent::Purchased {
+        contract: token.contract,
+        token_id: token.token_id,
+        price,
+        seller,
+        buyer,
+    })?;
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct MakeOfferParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+    amount: TokenPrice,
+}
+
+/// Offers to buy a token, listed or not. The offered amount must be attached and is held by the contract.
+/// Each bidder can have one offer per token, and a token has at most `MAX_OFFERS_PER_TOKEN` offers.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "make_offer",
+    parameter = "MakeOfferParameter",
+    mutable,
+    payable
+)]
+fn marketplace_make_offer<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    amount: Amount,
+) -> ContractResult<()> {
+    let param: MakeOfferParameter = ctx.parameter_cursor().get()?;
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+
+    let bidder = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    ensure!(param.amount != 0.into(), MarketplaceError::PriceTooLow.into());
+    ensure!(
+        amount == price_to_amount(param.amount),
+        MarketplaceError::InsufficientPayment.into()
+    );
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let mut offers = host.state_mut().offers.entry(token).or_insert_with(Vec::new);
+    ensure!(
+        offers.iter().all(|offer| offer.bidder != bidder),
+        MarketplaceError::OfferAlreadyExists.into()
+    );
+    ensure!(
+        offers.len() < MAX_OFFERS_PER_TOKEN,
+        MarketplaceError::TooManyOffers.into()
+    );
+    offers.push(Offer {
+        bidder,
+        amount: param.amount,
+    });
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct CancelOfferParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "cancel_offer",
+    parameter = "CancelOfferParameter",
+    mutable
+)]
+fn marketplace_cancel_offer<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let param: CancelOfferParameter = ctx.parameter_cursor().get()?;
+
+    let bidder = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let offer = host
+        .state_mut()
+        .take_offer(&token, &bidder)
+        .ok_or(MarketplaceError::OfferNotFound)?;
+
+    host.invoke_transfer(&bidder, price_to_amount(offer.amount))?;
+
+    Ok(())
+}
+
+#[derive(SchemaType, Serialize)]
+struct AcceptOfferParameter {
+    token_id: TokenId,
+    contract: ContractAddress,
+    bidder: AccountAddress,
+}
+
+/// Called by the token owner. The token is sent to the bidder, and the offered amount is paid out like a purchase.
+/// Any listing of the token is removed.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "accept_offer",
+    parameter = "AcceptOfferParameter",
+    mutable,
+    enable_logger
+)]
+fn marketplace_accept_offer<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let param: AcceptOfferParameter = ctx.parameter_cursor().get()?;
+
+    ensure!(!host.state().paused, MarketplaceError::ContractPaused.into());
+
+    let seller = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(MarketplaceError::Unauthorized.into()),
+    };
+
+    let balance = balance_of(host, &param.contract, param.token_id, Address::Account(seller))?;
+    ensure!(
+        balance != 0.into(),
+        MarketplaceError::NotTokenOwner.into()
+    );
+    ensure_marketplace_operator(ctx, host, &param.contract, Address::Account(seller))?;
+
+    let token = TokenIdentifier {
+        contract: param.contract,
+        token_id: param.token_id,
+    };
+
+    let state = host.state_mut();
+    ensure_cooled_down(state, &token, ctx.metadata().slot_time())?;
+    let offer = state
+        .take_offer(&token, &param.bidder)
+        .ok_or(MarketplaceError::OfferNotFound)?;
+    state.remove_listing(&token);
+
+    let transfer = Transfer::<TokenId, TokenPrice> {
+        token_id: param.token_id,
+        amount: 1.into(),
+        from: Address::Account(seller),
+        to: Receiver::Account(offer.bidder),
+        data: AdditionalData::empty(),
+    };
+
+    let parameter = TransferParams::from(vec![transfer]);
+
+    host.invoke_contract(
+        &(param.contract),
+        &parameter,
+        EntrypointName::new_unchecked("transfer"),
+        Amount::zero(),
+    )
+    .map_err(|_| MarketplaceError::TransferFailed)?;
+
+    settle_sale(ctx, host, &token, offer.amount, seller, offer.bidder, None, logger)
+}
+
+#[derive(SchemaType, Serialize)]
+struct CanAcceptOfferQuery {
+    token_id: TokenId,
+    contract: ContractAddress,
+    offerer: AccountAddress,
+}
+
+/// For each query, whether the sender could accept the offer right now: the offer exists,
+/// the sender owns the token and the marketplace is its operator. Offers do not expire.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_can_accept_offers",
+    parameter = "Vec<CanAcceptOfferQuery>",
+    return_value = "Vec<bool>",
+    mutable
+)]
+fn marketplace_view_can_accept_offers<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<bool>> {
+    let queries: Vec<CanAcceptOfferQuery> = ctx.parameter_cursor().get()?;
+
+    let seller = ctx.sender();
+    let marketplace = Address::Contract(ctx.self_address());
+
+    let mut result = Vec::with_capacity(queries.len());
+
+    for query in queries {
+        let token = TokenIdentifier {
+            contract: query.contract,
+            token_id: query.token_id,
+        };
+
+        let has_offer = match host.state().offers.get(&token) {
+            Some(offers) => offers.iter().any(|offer| offer.bidder == query.offerer),
+            None => false,
+        };
+
+        let can_accept = has_offer
+            && matches!(balance_of(host, &query.contract, query.token_id, seller), Ok(balance) if balance != 0.into())
+            && matches!(is_operator_of(host, &query.contract, seller, marketplace), Ok(true));
+
+        result.push(can_accept);
+    }
+
+    Ok(result)
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_recent_sales",
+    return_value = "Vec<SaleRecord>"
+)]
+fn marketplace_view_recent_sales<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<SaleRecord>> {
+    Ok(host.state().recent_sales.clone())
+}
+
+#[derive(SchemaType, Serialize)]
+struct ViewChangesSinceParameter {
+    sale_id: u64,
+}
+
+/// Sales with an id greater than `sale_id`, oldest first. Only sales still in `recent_sales` are returned.
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_changes_since",
+    parameter = "ViewChangesSinceParameter",
+    return_value = "Vec<SaleRecord>"
+)]
+fn marketplace_view_changes_since<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<SaleRecord>> {
+    let param: ViewChangesSinceParameter = ctx.parameter_cursor().get()?;
+
+    let sales = host
+        .state()
+        .recent_sales
+        .iter()
+        .filter(|sale| sale.sale_id > param.sale_id)
+        .cloned()
+        .collect();
+
+    Ok(sales)
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_record_sale",
+    return_value = "Option<SaleRecord>"
+)]
+fn marketplace_view_record_sale<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Option<SaleRecord>> {
+    Ok(host.state().max_sale.clone())
+}
+
+#[receive(
+    contract = "MarketplaceBeatoken",
+    name = "view_unique_buyers",
+    return_value = "u64"
+)]
+fn marketplace_view_unique_buyers<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    Ok(host.state().buyers.iter().count() as u64)
+}
+
+#[concordium_cfg_test]
+mod tests {
+    use super::*;
+    use concordium_std::test_infrastructure::*;
+
+    const OWNER: AccountAddress = AccountAddress([0u8; 32]);
+    const OWNER_ADDR: Address = Address::Account(OWNER);
+
+    const RECEIVER: AccountAddress = AccountAddress([1u8; 32]);
+    const OTHER_ACCOUNT: AccountAddress = AccountAddress([2u8; 32]);
 
     const NFT_CONTRACT: ContractAddress = ContractAddress{index: 42, subindex: 0};
+    const OTHER_NFT_CONTRACT: ContractAddress = ContractAddress{index: 43, subindex: 0};
+    const MARKETPLACE_CONTRACT: ContractAddress = ContractAddress{index: 7, subindex: 0};
+
+    const TOKEN1_ID: TokenId = TokenIdU32(1);
+    const TOKEN1_PRICE: TokenPrice = TokenAmountU64(1000);
+
+    const TOKEN2_ID: TokenId = TokenIdU32(2);
+
+    const SELLER: AccountAddress = AccountAddress([3u8; 32]);
+    const SELLER_ADDR: Address = Address::Account(SELLER);
+
+    fn token(token_id: TokenId) -> TokenIdentifier {
+        TokenIdentifier {
+            contract: NFT_CONTRACT,
+            token_id,
+        }
+    }
+
+    fn listing(price: TokenPrice) -> Listing {
+        Listing {
+            price,
+            seller: SELLER,
+            expiry: None,
+        }
+    }
+
+    fn purchase(host: &mut TestHost<State<TestStateApi>>, token_id: TokenId, to: AccountAddress, slot_time: u64) -> ContractResult<()> {
+        let param = PurchaseParameter {
+            token_id,
+            to,
+            contract: NFT_CONTRACT,
+        };
+        let param_bytes = to_bytes(&param);
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(slot_time));
+        ctx.set_parameter(&param_bytes);
+
+        let price = host.state().tokens_for_sale.get(&token(token_id)).map(|l| l.price).unwrap_or(0.into());
+        let amount = host.state().expected_payment(price);
+        host.set_self_balance(amount);
+
+        let mut logger = TestLogger::init();
+        marketplace_purchase(&ctx, host, amount, &mut logger)
+    }
+
+    fn mock_supports<S>(supported: bool) -> MockFn<S> {
+        MockFn::new_v1(move |_, _, _, _| {
+            let result = if supported { SupportResult::Support } else { SupportResult::NoSupport };
+            Ok((false, SupportsQueryResponse::from(vec![result])))
+        })
+    }
+
+    /// A host on which tokens of NFT_CONTRACT pass the checks of place_for_sale: the contract is allowed and
+    /// supports CIS2, and the seller owns the token.
+    fn listable_host() -> TestHost<State<TestStateApi>> {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        host.state_mut().allowed_contracts.insert(NFT_CONTRACT);
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(1));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), mock_supports(true));
+        host
+    }
+
+    fn mock_operator_of<S>(is_operator: bool) -> MockFn<S> {
+        MockFn::new_v1(move |_, _, _, _| Ok((false, OperatorOfQueryResponse::from(vec![is_operator]))))
+    }
+
+    fn mock_balance_of<S>(balance: u64) -> MockFn<S> {
+        MockFn::new_v1(move |_, _, _, _| {
+            Ok((false, BalanceOfQueryResponse::from(vec![TokenAmountU64(balance)])))
+        })
+    }
+
+    #[concordium_test]
+    fn test_init() {
+        // Setup the context
+        let mut ctx = TestInitContext::empty();
+        ctx.set_init_origin(OWNER);
+        let mut builder = TestStateBuilder::new();
+
+        let param = InitParameter {
+            admin: OTHER_ACCOUNT,
+            commission_bps: 250,
+            min_price: TokenAmountU64(10),
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        // Call the contract function.
+        let result = marketplace_init(&ctx, &mut builder);
+
+        // Check the result
+        let state = result.expect_report("Contract initialization failed");
+
+        // Check the state
+        claim_eq!(state.tokens_for_sale.iter().count(), 0, "No token should be listed for sale after initialization.");
+        claim_eq!(state.commission_bps, 250, "Commission should be set from the init parameter.");
+        claim_eq!(state.admin, OTHER_ACCOUNT, "The admin should be set from the init parameter.");
+        claim_eq!(state.min_price, TokenAmountU64(10), "Minimum price should be set from the init parameter.");
+    }
+
+    #[concordium_test]
+    fn test_init_invalid_commission() {
+        let mut ctx = TestInitContext::empty();
+        let mut builder = TestStateBuilder::new();
+
+        let param = InitParameter {
+            admin: OWNER,
+            commission_bps: MAX_COMMISSION_BPS + 1,
+            min_price: 0.into(),
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_init(&ctx, &mut builder);
+        claim!(
+            matches!(result, Err(ContractError(Cis2Error::Custom(MarketplaceError::InvalidCommission)))),
+            "Init with a commission above 100% should be rejected."
+        );
+    }
+
+    #[concordium_test]
+    fn test_set_commission() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param = SetCommissionParameter {
+            commission_bps: 500,
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_set_commission(&ctx, &mut host);
+        claim!(result.is_ok(), "Set commission results in rejection.");
+        claim_eq!(host.state().commission_bps, 500, "Commission should be updated.");
+
+        let param = SetCommissionParameter {
+            commission_bps: MAX_COMMISSION_BPS + 1,
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_set_commission(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::InvalidCommission.into()), "Commission above 100% should be rejected.");
+
+        host.state_mut().royalties.insert(NFT_CONTRACT, Royalty { recipient: OTHER_ACCOUNT, bps: 500 });
+        let param = SetCommissionParameter {
+            commission_bps: MAX_COMMISSION_BPS - 499,
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_set_commission(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::InvalidCommission.into()), "Commission and royalty above 100% should be rejected.");
+        claim_eq!(host.state().commission_bps, 500, "The commission should be unchanged.");
+
+        ctx.set_sender(SELLER_ADDR);
+        let result = marketplace_set_commission(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the owner may set the commission.");
+    }
+
+    #[concordium_test]
+    fn test_set_paused() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param_bytes = to_bytes(&SetPausedParameter { paused: true });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_set_paused(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the owner may pause the contract.");
+        claim!(!host.state().paused, "The contract should not be paused by default.");
+
+        ctx.set_sender(OWNER_ADDR);
+        let result = marketplace_set_paused(&ctx, &mut host);
+        claim!(result.is_ok(), "Set paused results in rejection.");
+        claim!(host.state().paused, "The contract should be paused.");
+    }
+
+    #[concordium_test]
+    fn test_set_min_price() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param_bytes = to_bytes(&SetMinPriceParameter { min_price: TOKEN1_PRICE });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_set_min_price(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the admin may set the minimum price.");
+
+        ctx.set_sender(OWNER_ADDR);
+        let result = marketplace_set_min_price(&ctx, &mut host);
+        claim!(result.is_ok(), "Set min price results in rejection.");
+        claim_eq!(host.state().min_price, TOKEN1_PRICE, "Minimum price should be updated.");
+    }
+
+    #[concordium_test]
+    fn test_reprice_all_by_seller() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        let other_token = token(TokenIdU32(3));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TokenAmountU64(2000)));
+        host.state_mut().insert_listing(other_token, Listing { seller: OTHER_ACCOUNT, ..listing(TOKEN1_PRICE) });
+
+        let param_bytes = to_bytes(&RepriceAllParameter { markup_bps: 1000, down: false });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_reprice_all_by_seller(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Reprice all by seller results in rejection.");
+
+        let price_of = |token: &TokenIdentifier| host.state().tokens_for_sale.get(token).map(|l| l.price);
+        claim_eq!(price_of(&token(TOKEN1_ID)), Some(TokenAmountU64(1100)), "The first listing should be 10% more expensive.");
+        claim_eq!(price_of(&token(TOKEN2_ID)), Some(TokenAmountU64(2200)), "The second listing should be 10% more expensive.");
+        claim_eq!(price_of(&other_token), Some(TOKEN1_PRICE), "Listings of other sellers should be unchanged.");
+        claim_eq!(logger.logs.len(), 2, "A PriceUpdated event should be logged per repriced listing.");
+    }
+
+    #[concordium_test]
+    fn test_reprice_all_by_seller_bounds() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TokenAmountU64(u64::MAX)));
+        host.state_mut().min_price = TokenAmountU64(950);
+
+        let param_bytes = to_bytes(&RepriceAllParameter { markup_bps: 1000, down: false });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_reprice_all_by_seller(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::PriceOverflow.into()), "A markup beyond the maximum price should be rejected.");
+
+        let param_bytes = to_bytes(&RepriceAllParameter { markup_bps: 1000, down: true });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_reprice_all_by_seller(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::PriceTooLow.into()), "A discount below the minimum price should be rejected.");
+
+        claim_eq!(host.state().tokens_for_sale.get(&token(TOKEN1_ID)).map(|l| l.price), Some(TOKEN1_PRICE), "No listing should be repriced.");
+    }
+
+    #[concordium_test]
+    fn test_min_price_enforced() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.state_mut().min_price = TOKEN1_PRICE;
+
+        let below = TokenAmountU64(TOKEN1_PRICE.0 - 1);
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: below, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::PriceTooLow.into()), "A price below the floor should be rejected.");
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "A price at the floor should be accepted.");
+
+        let param_bytes = to_bytes(&UpdatePriceParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, new_price: below });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::PriceTooLow.into()), "Updating below the floor should be rejected.");
+
+        let param_bytes = to_bytes(&UpdatePriceParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, new_price: TOKEN1_PRICE });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Updating to the floor should be accepted.");
+    }
+
+    #[concordium_test]
+    fn test_allowed_contracts() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param_bytes = to_bytes(&AllowedContractParameter { contract: NFT_CONTRACT });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_add_allowed_contract(&ctx, &mut host);
+        claim!(result.is_ok(), "Add allowed contract results in rejection.");
+        claim!(host.state().allowed_contracts.contains(&NFT_CONTRACT), "The contract should be allowed.");
+
+        let result = marketplace_remove_allowed_contract(&ctx, &mut host);
+        claim!(result.is_ok(), "Remove allowed contract results in rejection.");
+        claim!(!host.state().allowed_contracts.contains(&NFT_CONTRACT), "The contract should no longer be allowed.");
+
+        ctx.set_sender(SELLER_ADDR);
+        let result = marketplace_add_allowed_contract(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the admin may allow contracts.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_contract_not_allowed() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(OTHER_NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(1));
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: OTHER_NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::ContractNotAllowed.into()), "Listings on an unlisted contract should be rejected.");
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Listings on a whitelisted contract should be accepted.");
+    }
+
+    #[concordium_test]
+    fn test_lock_config() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let result = marketplace_lock_config(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the admin may lock the configuration.");
+
+        ctx.set_sender(OWNER_ADDR);
+        let result = marketplace_lock_config(&ctx, &mut host);
+        claim!(result.is_ok(), "Lock config results in rejection.");
+        claim!(host.state().config_locked, "The configuration should be locked.");
+
+        let commission_bytes = to_bytes(&SetCommissionParameter { commission_bps: 500 });
+        ctx.set_parameter(&commission_bytes);
+        claim_eq!(marketplace_set_commission(&ctx, &mut host), Err(MarketplaceError::ConfigLocked.into()), "Set commission should be rejected after locking.");
+
+        let min_price_bytes = to_bytes(&SetMinPriceParameter { min_price: TOKEN1_PRICE });
+        ctx.set_parameter(&min_price_bytes);
+        claim_eq!(marketplace_set_min_price(&ctx, &mut host), Err(MarketplaceError::ConfigLocked.into()), "Set min price should be rejected after locking.");
+
+        let ttl_bytes = to_bytes(&SetDefaultListingTtlParameter { ttl: Duration::from_millis(1) });
+        ctx.set_parameter(&ttl_bytes);
+        claim_eq!(marketplace_set_default_listing_ttl(&ctx, &mut host), Err(MarketplaceError::ConfigLocked.into()), "Set default listing ttl should be rejected after locking.");
+
+        let cooldown_bytes = to_bytes(&SetTokenPurchaseCooldownParameter { cooldown: Duration::from_millis(1) });
+        ctx.set_parameter(&cooldown_bytes);
+        claim_eq!(marketplace_set_token_purchase_cooldown(&ctx, &mut host), Err(MarketplaceError::ConfigLocked.into()), "Set purchase cooldown should be rejected after locking.");
+
+        let contract_bytes = to_bytes(&AllowedContractParameter { contract: NFT_CONTRACT });
+        ctx.set_parameter(&contract_bytes);
+        claim_eq!(marketplace_add_allowed_contract(&ctx, &mut host), Err(MarketplaceError::ConfigLocked.into()), "Allowing contracts should be rejected after locking.");
+        claim_eq!(marketplace_remove_allowed_contract(&ctx, &mut host), Err(MarketplaceError::ConfigLocked.into()), "Disallowing contracts should be rejected after locking.");
+
+        claim_eq!(host.state().commission_bps, 0, "The commission should be unchanged.");
+    }
+
+    #[concordium_test]
+    fn test_lock_config_keeps_trading() {
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().config_locked = true;
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale should still work after locking.");
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase should still work after locking.");
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(OWNER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        let param_bytes = to_bytes(&SetPausedParameter { paused: true });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_set_paused(&ctx, &mut host);
+        claim!(result.is_ok(), "Pausing should still work after locking.");
+    }
+
+    #[concordium_test]
+    fn test_transfer_admin() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param_bytes = to_bytes(&TransferAdminParameter { new_admin: OTHER_ACCOUNT });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_transfer_admin(&ctx, &mut host);
+        claim!(result.is_ok(), "Transfer admin results in rejection.");
+        claim_eq!(host.state().admin, OTHER_ACCOUNT, "The admin should be handed over.");
+
+        let param_bytes = to_bytes(&SetPausedParameter { paused: true });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_set_paused(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "The previous admin should lose its rights.");
+
+        ctx.set_sender(Address::Account(OTHER_ACCOUNT));
+        let result = marketplace_set_paused(&ctx, &mut host);
+        claim!(result.is_ok(), "The new admin should be able to administer the marketplace.");
+    }
+
+    #[concordium_test]
+    fn test_transfer_admin_not_admin() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param_bytes = to_bytes(&TransferAdminParameter { new_admin: SELLER });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_transfer_admin(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the admin may transfer the admin role.");
+        claim_eq!(host.state().admin, OWNER, "The admin should be unchanged.");
+    }
+
+    #[concordium_test]
+    fn test_paused_gates_trading() {
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+        host.state_mut().paused = true;
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+
+        let place_bytes = to_bytes(&PlaceForSaleParameter {
+            token_id: TokenIdU32(3),
+            price: TOKEN1_PRICE,
+            contract: NFT_CONTRACT,
+            expiry: None,
+        });
+        let update_bytes = to_bytes(&UpdatePriceParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+            new_price: TokenAmountU64(2000),
+        });
+        let withdraw_bytes = to_bytes(&WithdrawParameter {
+            token_id: TOKEN2_ID,
+            contract: NFT_CONTRACT,
+        });
+
+        ctx.set_parameter(&place_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::ContractPaused.into()), "Place for sale should be rejected while paused.");
+
+        ctx.set_parameter(&update_bytes);
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::ContractPaused.into()), "Update price should be rejected while paused.");
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim_eq!(result, Err(MarketplaceError::ContractPaused.into()), "Purchase should be rejected while paused.");
+
+        ctx.set_parameter(&withdraw_bytes);
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Withdraw should stay available while paused.");
+
+        host.state_mut().paused = false;
+
+        ctx.set_parameter(&place_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale results in rejection after unpausing.");
+
+        ctx.set_parameter(&update_bytes);
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Update price results in rejection after unpausing.");
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection after unpausing.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let param = PlaceForSaleParameter {
+            token_id: 1.into(),
+            price: 1000.into(),
+            contract: NFT_CONTRACT,
+            expiry: None,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale results in rejection.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "Expected exactly one token listed for sale");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_expired() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(1000));
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let mut param = PlaceForSaleParameter {
+            token_id: TOKEN1_ID,
+            price: TOKEN1_PRICE,
+            contract: NFT_CONTRACT,
+            expiry: Some(Timestamp::from_timestamp_millis(1000)),
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::ListingExpired.into()), "A listing that expires at once should be rejected.");
+
+        param.expiry = Some(Timestamp::from_timestamp_millis(1001));
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale with a future expiry results in rejection.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_batch() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let params: Vec<PlaceForSaleParameter> = (1..=3)
+            .map(|id| PlaceForSaleParameter {
+                token_id: TokenIdU32(id),
+                price: TOKEN1_PRICE,
+                contract: NFT_CONTRACT,
+                expiry: None,
+            })
+            .collect();
+
+        let param_bytes = to_bytes(&params);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale_batch(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale batch results in rejection.");
+
+        let view = marketplace_view_list_for_sale(&ctx, &host).expect_report("View list for sale results in rejection.");
+        let ids: Vec<TokenId> = view.tokens.iter().map(|t| t.id).collect();
+        claim_eq!(ids, vec![TokenIdU32(1), TokenIdU32(2), TokenIdU32(3)], "All three tokens should be listed.");
+        claim_eq!(logger.logs.len(), 3, "A Listed event should be logged per token.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_default_expiry() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(1000));
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.state_mut().default_listing_ttl = Duration::from_millis(500);
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale results in rejection.");
+
+        let explicit = Some(Timestamp::from_timestamp_millis(5000));
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN2_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: explicit });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale results in rejection.");
+
+        let expiry_of = |id: TokenId| host.state().tokens_for_sale.get(&token(id)).and_then(|l| l.expiry);
+        claim_eq!(expiry_of(TOKEN1_ID), Some(Timestamp::from_timestamp_millis(1500)), "A listing without expiry should get the default.");
+        claim_eq!(expiry_of(TOKEN2_ID), explicit, "An explicit expiry should be kept.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_large_price() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let price = TokenAmountU64(u64::from(u32::MAX) + 1);
+        let param = PlaceForSaleParameter { token_id: TOKEN1_ID, price, contract: NFT_CONTRACT, expiry: None };
+        let param_bytes = to_bytes(&param);
+        let parsed: PlaceForSaleParameter = from_bytes(&param_bytes).expect_report("Place for sale parameter should parse.");
+        claim_eq!(parsed.price, price, "The price should survive serialization.");
+
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale results in rejection.");
+
+        let view = marketplace_view_list_for_sale(&ctx, &host).expect_report("View list for sale results in rejection.");
+        claim_eq!(view.tokens.iter().map(|t| t.price).collect::<Vec<_>>(), vec![price], "A price above u32::MAX should be listed unchanged.");
+        claim_eq!(price_to_amount(price), Amount::from_micro_ccd(u64::from(u32::MAX) + 1), "The price should convert to the same amount of micro CCD.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_already_listed() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "The first listing results in rejection.");
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TokenAmountU64(1), contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::AlreadyListed.into()), "Listing the same token twice should be rejected.");
+
+        let price = host.state().tokens_for_sale.get(&token(TOKEN1_ID)).map(|l| l.price);
+        claim_eq!(price, Some(TOKEN1_PRICE), "The existing listing should be kept.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_unsupported_standard() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), mock_supports(false));
+
+        let param_bytes = to_bytes(&PlaceForSaleParameter { token_id: TOKEN1_ID, price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::UnsupportedStandard.into()), "Listings on a contract without CIS2 support should be rejected.");
+
+        // The test host panics on entrypoints that are not mocked, so mock the error the chain gives instead.
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), MockFn::returning_err::<()>(CallContractError::MissingEntrypoint));
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::UnsupportedStandard.into()), "Listings on a contract without a supports entrypoint should be rejected.");
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), mock_supports(true));
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Listings on a contract supporting CIS2 should be accepted.");
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_records_seller() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let param = PlaceForSaleParameter {
+            token_id: TOKEN1_ID,
+            price: TOKEN1_PRICE,
+            contract: NFT_CONTRACT,
+            expiry: None,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Place for sale by a non-owner results in rejection.");
+
+        let listing = host.state().tokens_for_sale.get(&token(TOKEN1_ID)).map(|l| *l);
+        claim_eq!(listing.map(|l| l.seller), Some(SELLER), "The sender should be recorded as the seller.");
+
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::Listed { contract: NFT_CONTRACT, token_id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER })],
+            "A Listed event should be logged."
+        );
+    }
+
+    #[concordium_test]
+    fn test_place_for_sale_not_token_owner() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(0));
+
+        let param = PlaceForSaleParameter {
+            token_id: TOKEN1_ID,
+            price: TOKEN1_PRICE,
+            contract: NFT_CONTRACT,
+            expiry: None,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::NotTokenOwner.into()), "Listing a token the sender does not own should be rejected.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "No token should be listed for sale");
+    }
+
+    #[concordium_test]
+    fn test_update_price() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let new_price = TokenAmountU64(1500);
+        let param = UpdatePriceParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+            new_price,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Update price results in rejection.");
+
+        let view = marketplace_view_list_for_sale(&ctx, &host).expect_report("View list for sale results in rejection.");
+        claim_eq!(view.tokens, vec![ViewStateToken{ contract: NFT_CONTRACT, id: TOKEN1_ID, price: new_price, seller: SELLER, expired: false }], "Only the price should have changed.");
+
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::PriceUpdated { contract: NFT_CONTRACT, token_id: TOKEN1_ID, price: new_price })],
+            "A PriceUpdated event should be logged."
+        );
+    }
+
+    #[concordium_test]
+    fn test_update_price_rejections() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let param = UpdatePriceParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+            new_price: TokenAmountU64(1500),
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the seller may update the price.");
+
+        let param = UpdatePriceParameter {
+            token_id: TOKEN2_ID,
+            contract: NFT_CONTRACT,
+            new_price: TokenAmountU64(1500),
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_update_price(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::TokenNotFound(TOKEN2_ID).into()), "Updating an unlisted token should be rejected.");
+    }
+
+    #[concordium_test]
+    fn test_withdraw() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let param = WithdrawParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Withdraw results in rejection.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "After withdraw there should be no tokens for sale.");
+
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::Withdrawn { contract: NFT_CONTRACT, token_id: TOKEN1_ID })],
+            "A Withdrawn event should be logged."
+        );
+    }
+
+    #[concordium_test]
+    fn test_withdraw_same_token_id_other_contract() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        let other_token = TokenIdentifier {
+            contract: OTHER_NFT_CONTRACT,
+            token_id: TOKEN1_ID,
+        };
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(other_token, listing(TOKEN1_PRICE));
+
+        let param = WithdrawParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Withdraw results in rejection.");
+
+        let remaining: Vec<TokenIdentifier> = host.state().tokens_for_sale.iter().map(|(token, _)| *token).collect();
+        claim_eq!(remaining, vec![other_token], "The same token ID on another contract should stay listed.");
+
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::TokenNotFound(TOKEN1_ID).into()), "Withdrawing an unlisted token should be rejected.");
+    }
+
+    #[concordium_test]
+    fn test_withdraw_by_seller() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let param = WithdrawParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Withdraw by the seller results in rejection.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "After withdraw there should be no tokens for sale.");
+    }
+
+    #[concordium_test]
+    fn test_withdraw_unauthorized() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(Address::Account(OTHER_ACCOUNT));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let param = WithdrawParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Withdraw by a third party should be rejected.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "The listing should remain.");
+    }
+
+    #[concordium_test]
+    fn test_withdraw_all() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        let other_token = token(TokenIdU32(3));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(other_token, Listing { seller: OTHER_ACCOUNT, ..listing(TOKEN1_PRICE) });
+
+        let result = marketplace_withdraw_all(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Withdraw all results in rejection.");
+
+        let remaining: Vec<TokenIdentifier> = host.state().tokens_for_sale.iter().map(|(token, _)| *token).collect();
+        claim_eq!(remaining, vec![other_token], "Only the other seller's listing should remain.");
+        claim_eq!(host.state().listings_count, 1, "The listing count should be updated.");
+        claim_eq!(logger.logs.len(), 2, "A Withdrawn event should be logged per removed listing.");
+
+        let result = marketplace_withdraw_all(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Withdraw all without listings should succeed.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "Nothing else should be removed.");
+    }
+
+    #[concordium_test]
+    fn test_on_token_transferred() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Contract(NFT_CONTRACT));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+
+        let param = TokenTransferredParameter {
+            token_id: TOKEN1_ID,
+            owner: SELLER,
+            balance: TokenAmountU64(0),
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_on_token_transferred(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Token transferred notification results in rejection.");
+
+        let param = TokenTransferredParameter {
+            token_id: TOKEN2_ID,
+            owner: SELLER,
+            balance: TokenAmountU64(1),
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_on_token_transferred(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Token transferred notification results in rejection.");
+
+        let remaining: Vec<TokenIdentifier> = host.state().tokens_for_sale.iter().map(|(token, _)| *token).collect();
+        claim_eq!(remaining, vec![token(TOKEN2_ID)], "Only the listing whose seller ran out of balance should be removed.");
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::Withdrawn { contract: NFT_CONTRACT, token_id: TOKEN1_ID })],
+            "A Withdrawn event should be logged."
+        );
+    }
+
+    #[concordium_test]
+    fn test_gift_batch() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+
+        host.setup_mock_entrypoint(
+            NFT_CONTRACT,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenId, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                let transfer = &params.0[0];
+                claim_eq!(transfer.from, SELLER_ADDR, "The token should be sent from the seller.");
+                claim!(transfer.to.address() == Address::Account(RECEIVER) || transfer.to.address() == Address::Account(OTHER_ACCOUNT), "The token should be sent to its recipient.");
+                Ok((false, ()))
+            }),
+        );
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let gifts = vec![
+            GiftParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, to: RECEIVER },
+            GiftParameter { token_id: TOKEN2_ID, contract: NFT_CONTRACT, to: OTHER_ACCOUNT },
+        ];
+        let param_bytes = to_bytes(&gifts);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_gift_batch(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Gift batch results in rejection.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "Both gifted listings should be removed.");
+        claim_eq!(host.get_transfers(), Vec::new(), "Gifting should not move any CCD.");
+        claim_eq!(
+            logger.logs,
+            vec![
+                to_bytes(&MarketplaceEvent::Gifted { contract: NFT_CONTRACT, token_id: TOKEN1_ID, to: RECEIVER }),
+                to_bytes(&MarketplaceEvent::Gifted { contract: NFT_CONTRACT, token_id: TOKEN2_ID, to: OTHER_ACCOUNT }),
+            ],
+            "A Gifted event should be logged per gifted token."
+        );
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(false));
+        let param_bytes = to_bytes(&vec![GiftParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, to: RECEIVER }]);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_gift_batch(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::MarketplaceNotOperator.into()), "Gifting without operator approval should be rejected.");
+    }
+
+    #[concordium_test]
+    fn test_gift_batch_failed_transfer() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+
+        host.setup_mock_entrypoint(
+            NFT_CONTRACT,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenId, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                if params.0[0].token_id == TOKEN2_ID {
+                    Err(CallContractError::Trap)
+                } else {
+                    Ok((false, ()))
+                }
+            }),
+        );
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let gifts = vec![
+            GiftParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, to: RECEIVER },
+            GiftParameter { token_id: TOKEN2_ID, contract: NFT_CONTRACT, to: RECEIVER },
+        ];
+        let param_bytes = to_bytes(&gifts);
+        ctx.set_parameter(&param_bytes);
+
+        // The rejection reverts the whole transaction on chain, including the first gift.
+        let result = marketplace_gift_batch(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::TransferFailed.into()), "A failed transfer should reject the whole batch.");
+    }
+
+    #[concordium_test]
+    fn test_view_time_since_last_sale() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(5000));
+
+        let elapsed = marketplace_view_time_since_last_sale(&ctx, &host).expect_report("View time since last sale results in rejection.");
+        claim_eq!(elapsed, None, "There should be no elapsed time before the first sale.");
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1000);
+        claim!(result.is_ok(), "Purchase results in rejection.");
+
+        let elapsed = marketplace_view_time_since_last_sale(&ctx, &host).expect_report("View time since last sale results in rejection.");
+        claim_eq!(elapsed, Some(4000), "The time since the sale should be measured from its slot time.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_expiry() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let expiring = Listing {
+            expiry: Some(Timestamp::from_timestamp_millis(1000)),
+            ..listing(TOKEN1_PRICE)
+        };
+        host.state_mut().insert_listing(token(TOKEN1_ID), expiring);
+        host.state_mut().insert_listing(token(TOKEN2_ID), expiring);
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1000);
+        claim!(result.is_ok(), "Purchase before the expiry results in rejection.");
+
+        let result = purchase(&mut host, TOKEN2_ID, RECEIVER, 1001);
+        claim_eq!(result, Err(MarketplaceError::ListingExpired.into()), "Purchase after the expiry should be rejected.");
+    }
+
+    #[concordium_test]
+    fn test_view_flags_expired_listings() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(2000));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), Listing {
+            expiry: Some(Timestamp::from_timestamp_millis(1000)),
+            ..listing(TOKEN1_PRICE)
+        });
+        host.state_mut().insert_listing(token(TOKEN2_ID), Listing {
+            expiry: Some(Timestamp::from_timestamp_millis(3000)),
+            ..listing(TOKEN1_PRICE)
+        });
+
+        let view = marketplace_view_list_for_sale(&ctx, &host).expect_report("View list for sale results in rejection.");
+        let flags: Vec<(TokenId, bool)> = view.tokens.iter().map(|t| (t.id, t.expired)).collect();
+        claim_eq!(flags, vec![(TOKEN1_ID, true), (TOKEN2_ID, false)], "Expired listings should still be shown, but flagged.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_removes_listing_before_transfer() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        // A re-entrant purchase during the transfer would find no listing and fail with TokenNotFound.
+        host.setup_mock_entrypoint(
+            NFT_CONTRACT,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|_, _, _, state: &mut State<TestStateApi>| {
+                claim!(state.tokens_for_sale.get(&token(TOKEN1_ID)).is_none(), "The listing should be removed before the transfer.");
+                Ok((false, ()))
+            }),
+        );
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_failed_transfer() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_err::<()>(CallContractError::Trap));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim_eq!(result, Err(MarketplaceError::TransferFailed.into()), "A failed transfer should reject the purchase.");
+
+        // The chain rolls back the removal of the listing when the purchase is rejected, the test host does not.
+        claim_eq!(host.get_transfers(), Vec::new(), "No CCD should be moved after a failed transfer.");
+        claim!(host.state().recent_sales.is_empty(), "No sale should be recorded after a failed transfer.");
+    }
+
+    #[concordium_test]
+    fn test_view_changes_since() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        for id in 1..=4 {
+            host.state_mut().insert_listing(token(TokenIdU32(id)), listing(TOKEN1_PRICE));
+            let result = purchase(&mut host, TokenIdU32(id), RECEIVER, u64::from(id));
+            claim!(result.is_ok(), "Purchase results in rejection.");
+        }
+
+        let mut ctx = TestReceiveContext::empty();
+        let param_bytes = to_bytes(&ViewChangesSinceParameter { sale_id: 2 });
+        ctx.set_parameter(&param_bytes);
+
+        let sales = marketplace_view_changes_since(&ctx, &host).expect_report("View changes since results in rejection.");
+        let ids: Vec<(u64, TokenId)> = sales.iter().map(|sale| (sale.sale_id, sale.token_id)).collect();
+        claim_eq!(ids, vec![(3, TokenIdU32(3)), (4, TokenIdU32(4))], "Only sales after the cursor should be returned.");
+    }
+
+    fn make_offer(host: &mut TestHost<State<TestStateApi>>, bidder: AccountAddress, amount: TokenPrice) -> ContractResult<()> {
+        let param_bytes = to_bytes(&MakeOfferParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+            amount,
+        });
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Account(bidder));
+        ctx.set_parameter(&param_bytes);
+
+        marketplace_make_offer(&ctx, host, price_to_amount(amount))
+    }
+
+    #[concordium_test]
+    fn test_make_offer() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let result = make_offer(&mut host, RECEIVER, TOKEN1_PRICE);
+        claim!(result.is_ok(), "Make offer results in rejection.");
+
+        let offers = host.state().offers.get(&token(TOKEN1_ID)).map(|offers| offers.clone());
+        claim_eq!(offers, Some(vec![Offer { bidder: RECEIVER, amount: TOKEN1_PRICE }]), "The offer should be recorded.");
+
+        let result = make_offer(&mut host, RECEIVER, TOKEN1_PRICE);
+        claim_eq!(result, Err(MarketplaceError::OfferAlreadyExists.into()), "A second offer from the same bidder should be rejected.");
+
+        let param_bytes = to_bytes(&MakeOfferParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, amount: TOKEN1_PRICE });
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Account(OTHER_ACCOUNT));
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_make_offer(&ctx, &mut host, Amount::zero());
+        claim_eq!(result, Err(MarketplaceError::InsufficientPayment.into()), "The offered amount must be attached.");
+
+        let param_bytes = to_bytes(&MakeOfferParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, amount: 0.into() });
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_make_offer(&ctx, &mut host, Amount::zero());
+        claim_eq!(result, Err(MarketplaceError::PriceTooLow.into()), "An offer of nothing should be rejected.");
+    }
+
+    #[concordium_test]
+    fn test_make_offer_capped() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        for i in 0..MAX_OFFERS_PER_TOKEN {
+            let result = make_offer(&mut host, AccountAddress([i as u8 + 10; 32]), TOKEN1_PRICE);
+            claim!(result.is_ok(), "Make offer results in rejection.");
+        }
+
+        let result = make_offer(&mut host, RECEIVER, TOKEN1_PRICE);
+        claim_eq!(result, Err(MarketplaceError::TooManyOffers.into()), "Offers on a token should be capped.");
+    }
+
+    #[concordium_test]
+    fn test_cancel_offer() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let result = make_offer(&mut host, RECEIVER, TOKEN1_PRICE);
+        claim!(result.is_ok(), "Make offer results in rejection.");
+        host.set_self_balance(price_to_amount(TOKEN1_PRICE));
+
+        let param_bytes = to_bytes(&CancelOfferParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT });
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Account(OTHER_ACCOUNT));
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_cancel_offer(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::OfferNotFound.into()), "Only the bidder's own offer can be cancelled.");
+
+        ctx.set_sender(Address::Account(RECEIVER));
+        let result = marketplace_cancel_offer(&ctx, &mut host);
+        claim!(result.is_ok(), "Cancel offer results in rejection.");
+
+        claim!(host.state().offers.get(&token(TOKEN1_ID)).is_none(), "The offer should be removed.");
+        claim_eq!(host.get_transfers(), vec![(RECEIVER, price_to_amount(TOKEN1_PRICE))], "The offered amount should be refunded.");
+    }
+
+    #[concordium_test]
+    fn test_accept_offer() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(1));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(false));
+        host.setup_mock_entrypoint(
+            NFT_CONTRACT,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenId, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                claim_eq!(params.0[0].from, SELLER_ADDR, "The token should be sent from its owner.");
+                claim_eq!(params.0[0].to.address(), Address::Account(RECEIVER), "The token should be sent to the bidder.");
+                Ok((false, ()))
+            }),
+        );
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TokenAmountU64(5000)));
+        let result = make_offer(&mut host, RECEIVER, TOKEN1_PRICE);
+        claim!(result.is_ok(), "Make offer results in rejection.");
+        host.set_self_balance(price_to_amount(TOKEN1_PRICE));
+
+        let param_bytes = to_bytes(&AcceptOfferParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT, bidder: RECEIVER });
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(1));
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_accept_offer(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::MarketplaceNotOperator.into()), "Accepting without operator approval should be rejected.");
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        let result = marketplace_accept_offer(&ctx, &mut host, &mut logger);
+        claim!(result.is_ok(), "Accept offer results in rejection.");
+
+        claim!(host.state().offers.get(&token(TOKEN1_ID)).is_none(), "The accepted offer should be removed.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "The listing of the sold token should be removed.");
+        claim_eq!(host.get_transfers(), vec![(SELLER, price_to_amount(TOKEN1_PRICE))], "The offered amount should be paid to the owner.");
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::Purchased { contract: NFT_CONTRACT, token_id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, buyer: RECEIVER })],
+            "A Purchased event should be logged."
+        );
+
+        let result = marketplace_accept_offer(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::OfferNotFound.into()), "An offer can only be accepted once.");
+    }
+
+    fn buy(host: &mut TestHost<State<TestStateApi>>, token_id: TokenId, amount: Amount, slot_time: u64) -> ContractResult<()> {
+        let param_bytes = to_bytes(&BuyParameter {
+            token_id,
+            contract: NFT_CONTRACT,
+        });
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Account(RECEIVER));
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(slot_time));
+        ctx.set_parameter(&param_bytes);
+
+        host.set_self_balance(amount);
+
+        let mut logger = TestLogger::init();
+        marketplace_buy(&ctx, host, amount, &mut logger)
+    }
+
+    #[concordium_test]
+    fn test_buy() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(
+            NFT_CONTRACT,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenId, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                claim_eq!(params.0[0].from, SELLER_ADDR, "The token should be sent from the seller.");
+                claim_eq!(params.0[0].to.address(), Address::Account(RECEIVER), "The token should be sent to the buyer.");
+                Ok((false, ()))
+            }),
+        );
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 1);
+        claim!(result.is_ok(), "Buy results in rejection.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "The bought token should no longer be for sale.");
+        claim_eq!(host.get_transfers(), vec![(SELLER, price_to_amount(TOKEN1_PRICE))], "The payment should be forwarded to the seller.");
+        claim!(host.state().buyers.contains(&RECEIVER), "The buyer should be recorded.");
+    }
+
+    #[concordium_test]
+    fn test_buy_cooldown() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().token_purchase_cooldown = Duration::from_millis(1000);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 1000);
+        claim!(result.is_ok(), "The first buy results in rejection.");
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 1999);
+        claim_eq!(result, Err(MarketplaceError::TooFrequent.into()), "Buying again within the cooldown should be rejected.");
+
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 2000);
+        claim!(result.is_ok(), "Buying again after the cooldown results in rejection.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_cooldown() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().token_purchase_cooldown = Duration::from_millis(1000);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 1000);
+        claim!(result.is_ok(), "The buy results in rejection.");
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1999);
+        claim_eq!(result, Err(MarketplaceError::TooFrequent.into()), "The cooldown should also apply to purchase.");
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 2000);
+        claim!(result.is_ok(), "Purchasing after the cooldown results in rejection.");
+    }
+
+    #[concordium_test]
+    fn test_buy_incorrect_amount() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TokenAmountU64(TOKEN1_PRICE.0 - 1)), 1);
+        claim_eq!(result, Err(MarketplaceError::InsufficientPayment.into()), "Buy with an incorrect amount should be rejected.");
+        claim_eq!(host.get_transfers(), Vec::new(), "No CCD should be moved.");
+    }
+
+    #[concordium_test]
+    fn test_buy_failed_transfer() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_err::<()>(CallContractError::Trap));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 1);
+        claim_eq!(result, Err(MarketplaceError::TransferFailed.into()), "A failed transfer should be rejected.");
+        claim_eq!(host.get_transfers(), Vec::new(), "No CCD should be moved after a failed transfer.");
+    }
+
+    #[concordium_test]
+    fn test_buy_marketplace_not_operator() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(false));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = buy(&mut host, TOKEN1_ID, price_to_amount(TOKEN1_PRICE), 1);
+        claim_eq!(result, Err(MarketplaceError::MarketplaceNotOperator.into()), "Buying without operator approval should be rejected.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "The listing should be kept.");
+        claim_eq!(host.get_transfers(), Vec::new(), "No CCD should be moved.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_marketplace_not_operator() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(false));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim_eq!(result, Err(MarketplaceError::MarketplaceNotOperator.into()), "Purchasing without operator approval should be rejected.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "The listing should be kept.");
+    }
+
+    #[concordium_test]
+    fn test_view_can_accept_offers() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let token3_id = TokenIdU32(3);
+        for id in [TOKEN1_ID, token3_id] {
+            let offer = vec![Offer { bidder: RECEIVER, amount: TOKEN1_PRICE }];
+            host.state_mut().offers.insert(token(id), offer);
+        }
+
+        // The seller no longer owns token 3.
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), MockFn::new_v1(move |parameter, _, _, _| {
+            let query: BalanceOfQueryParams<TokenId> = from_bytes(parameter.0).expect_report("Invalid balanceOf parameter");
+            let balance = if query.queries[0].token_id == token3_id { 0 } else { 1 };
+            Ok((false, BalanceOfQueryResponse::from(vec![TokenAmountU64(balance)])))
+        }));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), MockFn::new_v1(|_, _, _, _| {
+            Ok((false, OperatorOfQueryResponse::from(vec![true])))
+        }));
+
+        let queries = vec![
+            CanAcceptOfferQuery { token_id: TOKEN1_ID, contract: NFT_CONTRACT, offerer: RECEIVER },
+            CanAcceptOfferQuery { token_id: TOKEN1_ID, contract: NFT_CONTRACT, offerer: OTHER_ACCOUNT },
+            CanAcceptOfferQuery { token_id: TOKEN2_ID, contract: NFT_CONTRACT, offerer: RECEIVER },
+            CanAcceptOfferQuery { token_id: token3_id, contract: NFT_CONTRACT, offerer: RECEIVER },
+        ];
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        let param_bytes = to_bytes(&queries);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_view_can_accept_offers(&ctx, &mut host);
+        claim_eq!(result, Ok(vec![true, false, false, false]), "Only the existing offer on an owned token should be acceptable.");
+    }
+
+    #[concordium_test]
+    fn test_set_royalty() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(OWNER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        host.state_mut().commission_bps = 250;
+
+        let param_bytes = to_bytes(&SetRoyaltyParameter { contract: NFT_CONTRACT, recipient: OTHER_ACCOUNT, bps: MAX_COMMISSION_BPS - 249 });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_set_royalty(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::InvalidRoyalty.into()), "Royalty and commission above 100% should be rejected.");
+
+        let param_bytes = to_bytes(&SetRoyaltyParameter { contract: NFT_CONTRACT, recipient: OTHER_ACCOUNT, bps: 500 });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_set_royalty(&ctx, &mut host);
+        claim!(result.is_ok(), "Set royalty results in rejection.");
+
+        let royalty = host.state().royalties.get(&NFT_CONTRACT).map(|royalty| *royalty);
+        claim_eq!(royalty, Some(Royalty { recipient: OTHER_ACCOUNT, bps: 500 }), "The royalty should be stored for the contract.");
+
+        ctx.set_sender(SELLER_ADDR);
+        let result = marketplace_set_royalty(&ctx, &mut host);
+        claim_eq!(result, Err(MarketplaceError::Unauthorized.into()), "Only the admin may set royalties.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_with_royalty() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().commission_bps = 250;
+        host.state_mut().royalties.insert(NFT_CONTRACT, Royalty { recipient: OTHER_ACCOUNT, bps: 500 });
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection.");
+
+        claim_eq!(
+            host.get_transfers(),
+            vec![
+                (SELLER, Amount::from_micro_ccd(925)),
+                (OWNER, Amount::from_micro_ccd(25)),
+                (OTHER_ACCOUNT, Amount::from_micro_ccd(50)),
+            ],
+            "The seller, the admin and the creator should each get their share."
+        );
+    }
+
+    fn purchase_batch(host: &mut TestHost<State<TestStateApi>>, token_ids: &[TokenId], amount: Amount) -> ContractResult<()> {
+        let purchases: Vec<PurchaseParameter> = token_ids
+            .iter()
+            .map(|token_id| PurchaseParameter {
+                token_id: *token_id,
+                to: RECEIVER,
+                contract: NFT_CONTRACT,
+            })
+            .collect();
+        let param_bytes = to_bytes(&purchases);
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(OWNER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(1));
+        ctx.set_parameter(&param_bytes);
+
+        host.set_self_balance(amount);
+
+        let mut logger = TestLogger::init();
+        marketplace_purchase_batch(&ctx, host, amount, &mut logger)
+    }
+
+    #[concordium_test]
+    fn test_purchase_batch() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TokenAmountU64(2000)));
+
+        let result = purchase_batch(&mut host, &[TOKEN1_ID, TOKEN2_ID], price_to_amount(TOKEN1_PRICE));
+        claim_eq!(result, Err(MarketplaceError::InsufficientPayment.into()), "The attached amount must cover the whole batch.");
+
+        let result = purchase_batch(&mut host, &[TOKEN1_ID, TOKEN2_ID], Amount::from_micro_ccd(3000));
+        claim!(result.is_ok(), "Purchase batch results in rejection.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "Both tokens should be sold.");
+        claim_eq!(
+            host.get_transfers(),
+            vec![(SELLER, Amount::from_micro_ccd(1000)), (SELLER, Amount::from_micro_ccd(2000))],
+            "The seller should be paid for each token."
+        );
+    }
+
+    #[concordium_test]
+    fn test_purchase_batch_missing_item() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let result = purchase_batch(&mut host, &[TOKEN1_ID, TOKEN2_ID], Amount::from_micro_ccd(2000));
+        claim_eq!(result, Err(MarketplaceError::TokenNotFound(TOKEN2_ID).into()), "A missing item should reject the whole batch.");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "No item should be sold.");
+        claim_eq!(host.get_transfers(), Vec::new(), "No CCD should be moved.");
+    }
+
+    #[concordium_test]
+    fn test_purchase() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(1));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        host.setup_mock_entrypoint(
+            NFT_CONTRACT,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenId, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                claim_eq!(params.0[0].from, SELLER_ADDR, "The token should be sent from the seller of the listing.");
+                Ok((false, ()))
+            }),
+        );
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), MockFn::new_v1(|parameter, _, _, _| {
+            let query: OperatorOfQueryParams = from_bytes(parameter.0).expect_report("Invalid operatorOf parameter");
+            Ok((false, OperatorOfQueryResponse::from(vec![query.queries[0].owner == SELLER_ADDR])))
+        }));
+
+        let param = PurchaseParameter {
+            token_id: TOKEN1_ID,
+            to: RECEIVER,
+            contract: NFT_CONTRACT,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let amount = price_to_amount(TOKEN1_PRICE);
+        host.set_self_balance(amount);
+
+        let result = marketplace_purchase(&ctx, &mut host, amount, &mut logger);
+        claim!(result.is_ok(), "Purchase results in rejection");
+
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "There should be no tokens for sale left");
+        claim_eq!(host.get_transfers(), vec![(SELLER, amount)], "The payment should be forwarded to the seller");
+
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::Purchased { contract: NFT_CONTRACT, token_id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, buyer: RECEIVER })],
+            "A Purchased event should be logged."
+        );
+
+        let result = marketplace_purchase(&ctx, &mut host, amount, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::TokenNotFound(TOKEN1_ID).into()), "Purchasing a sold token should be rejected.");
+    }
+
+    #[concordium_test]
+    fn test_error_codes() {
+        let reject = Reject::from(ContractError::from(MarketplaceError::ParseParams));
+        claim_eq!(reject.error_code.get(), -1, "The first variant should map to -1.");
+
+        let reject = Reject::from(ContractError(Cis2Error::InvalidTokenId));
+        claim_eq!(reject.error_code.get(), -42000001, "The standard CIS2 errors should keep their codes.");
+
+        let reject = Reject::from(ContractError::from(MarketplaceError::TooFrequent));
+        claim_eq!(reject.error_code.get(), -20, "The variants after NotOperator should keep their codes.");
+
+        let reject = Reject::from(ContractError::from(MarketplaceError::TooManyOffers));
+        claim_eq!(reject.error_code.get(), -26, "The last variant should map to -26.");
+
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        let param = WithdrawParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
+        let reject = Reject::from(result.expect_err_report("Withdrawing an unlisted token should be rejected."));
+        claim_eq!(reject.error_code.get(), -2, "The second variant should map to -2.");
+        claim_eq!(reject.return_value, Some(to_bytes(&MarketplaceError::TokenNotFound(TOKEN1_ID))), "The token ID should be returned to the caller.");
+    }
+
+    #[concordium_test]
+    fn test_purchase_commission() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().commission_bps = 250;
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection");
+
+        claim_eq!(
+            host.get_transfers(),
+            vec![(SELLER, Amount::from_micro_ccd(975)), (OWNER, Amount::from_micro_ccd(25))],
+            "A 2.5% commission should go to the owner and the rest to the seller"
+        );
+    }
+
+    #[concordium_test]
+    fn test_purchase_with_payment_token() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let payment_contract = ContractAddress { index: 44, subindex: 0 };
+        host.state_mut().payment_token = Some(PaymentToken {
+            contract: payment_contract,
+            token_id: TokenIdVec(vec![1]),
+        });
+        host.state_mut().commission_bps = 250;
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        host.setup_mock_entrypoint(
+            payment_contract,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenIdVec, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                claim_eq!(params.0.len(), 2, "The seller and the commission should be paid.");
+                claim_eq!(params.0[0].from, OWNER_ADDR, "The price should be paid by the admin calling purchase.");
+                claim_eq!(params.0[0].to.address(), SELLER_ADDR, "The seller should be paid first.");
+                claim_eq!(params.0[0].amount, TokenAmountU64(975), "The seller should get the price minus the commission.");
+                claim_eq!(params.0[1].to.address(), OWNER_ADDR, "The commission should go to the owner.");
+                claim_eq!(params.0[1].amount, TokenAmountU64(25), "A 2.5% commission should be paid.");
+                claim_eq!(params.0[1].token_id, TokenIdVec(vec![1]), "The payment token should be transferred.");
+                Ok((false, ()))
+            }),
+        );
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection");
+
+        claim_eq!(host.get_transfers(), Vec::new(), "No CCD should be moved.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "The token should no longer be for sale");
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().payment_token = None;
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection");
+        claim_eq!(
+            host.get_transfers(),
+            vec![(SELLER, Amount::from_micro_ccd(975)), (OWNER, Amount::from_micro_ccd(25))],
+            "Without a payment token the price should be paid in CCD"
+        );
+    }
+
+    #[concordium_test]
+    fn test_purchase_with_payment_token_does_not_charge_recipient() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let payment_contract = ContractAddress { index: 44, subindex: 0 };
+        host.state_mut().payment_token = Some(PaymentToken {
+            contract: payment_contract,
+            token_id: TokenIdVec(vec![1]),
+        });
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+        // OTHER_ACCOUNT made the marketplace an operator to use buy, the payment token would let it move their tokens.
+        host.setup_mock_entrypoint(
+            payment_contract,
+            EntrypointName::new_unchecked("transfer").into(),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let params: TransferParams<TokenIdVec, TokenPrice> = from_bytes(parameter.0).expect_report("Transfer parameter should parse.");
+                for transfer in params.0.iter() {
+                    claim!(transfer.from != Address::Account(OTHER_ACCOUNT), "The recipient of the token should not be charged.");
+                }
+                Ok((false, ()))
+            }),
+        );
+
+        let result = purchase(&mut host, TOKEN1_ID, OTHER_ACCOUNT, 1);
+        claim!(result.is_ok(), "Purchase results in rejection");
+    }
+
+    #[concordium_test]
+    fn test_purchase_zero_commission() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let result = purchase(&mut host, TOKEN1_ID, RECEIVER, 1);
+        claim!(result.is_ok(), "Purchase results in rejection");
+
+        claim_eq!(host.get_transfers(), vec![(SELLER, Amount::from_micro_ccd(1000))], "Without commission the seller receives the full price");
+    }
+
+    #[concordium_test]
+    fn test_purchase_incorrect_payment() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_owner(OWNER);
+        ctx.set_sender(OWNER_ADDR);
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(1));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let param = PurchaseParameter {
+            token_id: TOKEN1_ID,
+            to: RECEIVER,
+            contract: NFT_CONTRACT,
+        };
 
-    const TOKEN1_ID: TokenId = TokenIdU32(1);
-    const TOKEN1_PRICE: TokenPrice = TokenAmountU32(1000);
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
 
-    const TOKEN2_ID: TokenId = TokenIdU32(2);
+        let price = price_to_amount(TOKEN1_PRICE);
+        for amount in [price - Amount::from_micro_ccd(1), price + Amount::from_micro_ccd(1)] {
+            host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+            host.set_self_balance(amount);
+
+            let result = marketplace_purchase(&ctx, &mut host, amount, &mut logger);
+            claim_eq!(result, Err(MarketplaceError::InsufficientPayment.into()), "Purchase with an incorrect amount should be rejected");
+            claim!(host.get_transfers().is_empty(), "No payment should be forwarded");
+        }
+    }
 
     #[concordium_test]
-    fn test_init() {
-        // Setup the context
-        let ctx = TestInitContext::empty();
-        let mut builder = TestStateBuilder::new();
+    fn test_view_unique_buyers() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
 
-        // Call the contract function.
-        let result = marketplace_init(&ctx, &mut builder);
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
 
-        // Check the result
-        let state = result.expect_report("Contract initialization failed");
+        let buyers = [RECEIVER, OTHER_ACCOUNT, RECEIVER];
+        for (i, buyer) in buyers.iter().enumerate() {
+            let token_id = TokenIdU32(i as u32);
+            host.state_mut().insert_listing(token(token_id), listing(TOKEN1_PRICE));
 
-        // Check the state
-        claim_eq!(state.tokens_for_sale.iter().count(), 0, "No token should be listed for sale after initialization.");
+            let result = purchase(&mut host, token_id, *buyer, 1);
+            claim!(result.is_ok(), "Purchase results in rejection");
+
+            let expected = if i == 0 { 1 } else { 2 };
+            let count = marketplace_view_unique_buyers(&TestReceiveContext::empty(), &host).expect_report("View unique buyers results in rejection.");
+            claim_eq!(count, expected, "Unexpected number of unique buyers.");
+        }
     }
 
     #[concordium_test]
-    fn test_place_for_sale() {
+    fn test_view_record_sale() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        let prices = [TokenAmountU64(500), TokenAmountU64(2000), TokenAmountU64(1000)];
+        for (i, price) in prices.iter().enumerate() {
+            let token_id = TokenIdU32(i as u32);
+            host.state_mut().insert_listing(token(token_id), listing(*price));
+
+            let result = purchase(&mut host, token_id, RECEIVER, 1);
+            claim!(result.is_ok(), "Purchase results in rejection");
+        }
+
+        let record = marketplace_view_record_sale(&TestReceiveContext::empty(), &host)
+            .expect_report("View record sale results in rejection.")
+            .expect_report("A record sale should exist.");
+        claim_eq!(record.token_id, TokenIdU32(1), "The record should be the largest sale.");
+        claim_eq!(record.price, TokenAmountU64(2000), "The record price should not be replaced by a smaller sale.");
+    }
+
+    #[concordium_test]
+    fn test_recent_sales_evicts_oldest() {
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), mock_operator_of(true));
+
+        for i in 0..=MAX_RECENT_SALES as u32 {
+            host.state_mut().insert_listing(token(TokenIdU32(i)), listing(TOKEN1_PRICE));
+
+            let result = purchase(&mut host, TokenIdU32(i), RECEIVER, i.into());
+            claim!(result.is_ok(), "Purchase results in rejection");
+        }
+
+        let ctx = TestReceiveContext::empty();
+        let sales = marketplace_view_recent_sales(&ctx, &host).expect_report("View recent sales results in rejection.");
+        claim_eq!(sales.len(), MAX_RECENT_SALES, "Recent sales should be capped.");
+        claim_eq!(sales[0].token_id, TokenIdU32(1), "The oldest sale should have been evicted.");
+        claim_eq!(sales[MAX_RECENT_SALES - 1].token_id, TokenIdU32(MAX_RECENT_SALES as u32), "The newest sale should be last.");
+    }
+
+    #[concordium_test]
+    fn test_view_tokens_for_sale() {
         let mut ctx = TestReceiveContext::empty();
-        ctx.set_owner(OWNER);
-        ctx.set_sender(OWNER_ADDR);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
 
         let mut state_builder = TestStateBuilder::new();
-        let mut host = TestHost::new(State::empty(&mut state_builder), state_builder);
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
 
-        let param = PlaceForSaleParameter {
-            token_id: 1.into(),
-            price: 1000.into(),
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        
+        let result = marketplace_view_list_for_sale(&ctx, &host);
+
+        let view = result.expect_report("View list for sale results in rejection.");
+        claim_eq!(view.tokens, vec![ViewStateToken{ contract: NFT_CONTRACT, id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, expired: false }], "Results should contain TOKEN1.");
+    }
+
+
+    #[concordium_test]
+    fn test_view_listing() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let param = ViewListingParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
         };
 
         let param_bytes = to_bytes(&param);
         ctx.set_parameter(&param_bytes);
 
-        let result = marketplace_place_for_sale(&ctx, &mut host);
-        claim!(result.is_ok(), "Place for sale results in rejection.");
+        let result = marketplace_view_listing(&ctx, &host).expect_report("View listing results in rejection.");
+        claim_eq!(
+            result,
+            Some(ViewStateToken{ contract: NFT_CONTRACT, id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, expired: false }),
+            "The listed token should be returned."
+        );
 
-        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "Expected exactly one token listed for sale");
+        let param = ViewListingParameter {
+            token_id: TOKEN2_ID,
+            contract: NFT_CONTRACT,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_view_listing(&ctx, &host).expect_report("View listing of an unlisted token results in rejection.");
+        claim!(result.is_none(), "An unlisted token should return None.");
     }
 
     #[concordium_test]
-    fn test_withdraw() {
+    fn test_view_list_for_sale_paged() {
         let mut ctx = TestReceiveContext::empty();
-        ctx.set_owner(OWNER);
-        ctx.set_sender(OWNER_ADDR);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
 
         let mut state_builder = TestStateBuilder::new();
-        let mut host = TestHost::new(State::empty(&mut state_builder), state_builder);
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        for id in 1..=5 {
+            host.state_mut().insert_listing(token(TokenIdU32(id)), listing(TOKEN1_PRICE));
+        }
 
-        host.state_mut().tokens_for_sale.insert(TOKEN1_ID, TOKEN1_PRICE);
+        let all = marketplace_view_list_for_sale(&ctx, &host).expect_report("View list for sale results in rejection.");
 
-        let param = WithdrawParameter {
-            token_id: TOKEN1_ID,
+        let param = ViewPagedParameter {
+            skip: 2,
+            take: 2,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let page = marketplace_view_list_for_sale_paged(&ctx, &host).expect_report("View list for sale paged results in rejection.");
+        claim_eq!(page.tokens, all.tokens[2..4].to_vec(), "The page should contain the third and fourth listing.");
+        claim!(page.has_more, "There should be more listings after the page.");
+
+        let param = ViewPagedParameter {
+            skip: 4,
+            take: 2,
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let page = marketplace_view_list_for_sale_paged(&ctx, &host).expect_report("View list for sale paged results in rejection.");
+        claim_eq!(page.tokens, all.tokens[4..].to_vec(), "The last page should contain the remaining listing.");
+        claim!(!page.has_more, "There should be no more listings after the last page.");
+    }
+
+    #[concordium_test]
+    fn test_view_compact() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+
+        let view = marketplace_view_list_for_sale(&ctx, &host).expect_report("View list for sale results in rejection.");
+        let compact = marketplace_view_compact(&ctx, &host).expect_report("View compact results in rejection.");
+
+        let expected: Vec<(TokenIdentifier, TokenPrice)> = view
+            .tokens
+            .iter()
+            .map(|t| (TokenIdentifier { contract: t.contract, token_id: t.id }, t.price))
+            .collect();
+        claim_eq!(compact, expected, "Compact view should match view_list_for_sale.");
+    }
+
+    #[concordium_test]
+    fn test_get_listed_for_sale() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        
+        let param = GetListedParameter{
+            tokens: vec![token(TOKEN1_ID), token(TOKEN2_ID)],
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_get_listed_for_sale(&ctx, &host);
+
+        let view = result.expect_report("Get listed for sale results in rejection.");
+        claim_eq!(view.tokens, vec![ViewStateToken{ contract: NFT_CONTRACT, id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, expired: false }], "Results should contain only TOKEN1.");
+    }
+
+    #[concordium_test]
+    fn test_view_affordable() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TokenAmountU64(5000)));
+
+        let param = ViewAffordableParameter {
+            budget: TOKEN1_PRICE,
         };
 
         let param_bytes = to_bytes(&param);
         ctx.set_parameter(&param_bytes);
 
-        let result = marketplace_withdraw(&ctx, &mut host);
+        let result = marketplace_view_affordable(&ctx, &host);
+
+        let view = result.expect_report("View affordable results in rejection.");
+        claim_eq!(view.tokens, vec![ViewStateToken{ contract: NFT_CONTRACT, id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, expired: false }], "Results should contain only TOKEN1.");
+    }
+
+    #[concordium_test]
+    fn test_view_config() {
+        let ctx = TestReceiveContext::empty();
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        host.state_mut().commission_bps = 250;
+        host.state_mut().min_price = TokenAmountU64(10);
+        host.state_mut().paused = true;
+        host.state_mut().default_listing_ttl = Duration::from_millis(1000);
+        host.state_mut().allowed_contracts.insert(NFT_CONTRACT);
+        host.state_mut().allowed_contracts.insert(OTHER_NFT_CONTRACT);
+        host.state_mut().royalties.insert(NFT_CONTRACT, Royalty { recipient: OTHER_ACCOUNT, bps: 100 });
+
+        let config = marketplace_view_config(&ctx, &host).expect_report("View config results in rejection.");
+        claim_eq!(
+            config,
+            ConfigView {
+                admin: OWNER,
+                commission_bps: 250,
+                min_price: TokenAmountU64(10),
+                paused: true,
+                config_locked: false,
+                default_listing_ttl: Duration::from_millis(1000),
+                token_purchase_cooldown: Duration::from_millis(0),
+                payment_token: None,
+                allowed_contracts_count: 2,
+                royalties_count: 1,
+            },
+            "The view should report the current configuration."
+        );
+    }
+
+    #[concordium_test]
+    fn test_view_listings_count() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(SELLER_ADDR);
+
+        let mut host = listable_host();
+        let mut logger = TestLogger::init();
+
+        let placements: Vec<Vec<u8>> = [1, 2, 3]
+            .iter()
+            .map(|id| to_bytes(&PlaceForSaleParameter { token_id: TokenIdU32(*id), price: TOKEN1_PRICE, contract: NFT_CONTRACT, expiry: None }))
+            .collect();
+
+        for param_bytes in placements.iter() {
+            ctx.set_parameter(param_bytes);
+            let result = marketplace_place_for_sale(&ctx, &mut host, &mut logger);
+            claim!(result.is_ok(), "Place for sale results in rejection.");
+        }
+
+        let count = marketplace_view_listings_count(&ctx, &host).expect_report("View listings count results in rejection.");
+        claim_eq!(count, 3, "Every placed token should be counted.");
+
+        let param_bytes = to_bytes(&WithdrawParameter { token_id: TOKEN1_ID, contract: NFT_CONTRACT });
+        ctx.set_parameter(&param_bytes);
+        let result = marketplace_withdraw(&ctx, &mut host, &mut logger);
         claim!(result.is_ok(), "Withdraw results in rejection.");
 
-        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "After withdraw there should be no tokens for sale.");
+        let count = marketplace_view_listings_count(&ctx, &host).expect_report("View listings count results in rejection.");
+        claim_eq!(count, 2, "A withdrawn listing should no longer be counted.");
+        claim_eq!(count, host.state().tokens_for_sale.iter().count() as u64, "The cached count should match the listings.");
     }
 
     #[concordium_test]
-    fn test_purchase() {
+    fn test_view_listings_by_seller() {
         let mut ctx = TestReceiveContext::empty();
-        ctx.set_owner(OWNER);
-        ctx.set_sender(OWNER_ADDR);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
 
         let mut state_builder = TestStateBuilder::new();
-        let mut host = TestHost::new(State::empty(&mut state_builder), state_builder);
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
 
-        host.state_mut().tokens_for_sale.insert(TOKEN1_ID, TOKEN1_PRICE);
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), Listing { seller: OTHER_ACCOUNT, ..listing(TOKEN1_PRICE) });
 
-        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("transfer").into(), MockFn::returning_ok(0));
+        let param_bytes = to_bytes(&SELLER);
+        ctx.set_parameter(&param_bytes);
 
-        let param = PurchaseParameter {
-            token_id: TOKEN1_ID,
-            from: OWNER,
-            to: RECEIVER,
+        let view = marketplace_view_listings_by_seller(&ctx, &host).expect_report("View listings by seller results in rejection.");
+        claim_eq!(view.tokens, vec![ViewStateToken{ contract: NFT_CONTRACT, id: TOKEN1_ID, price: TOKEN1_PRICE, seller: SELLER, expired: false }], "Results should contain only the seller's listings.");
+    }
+
+    #[concordium_test]
+    fn test_view_contract_compatible() {
+        let mut ctx = TestReceiveContext::empty();
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let param = ViewContractCompatibleParameter {
             contract: NFT_CONTRACT,
         };
 
         let param_bytes = to_bytes(&param);
         ctx.set_parameter(&param_bytes);
 
-        let result = marketplace_purchase(&ctx, &mut host);
-        claim!(result.is_ok(), "Purchase results in rejection");
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), MockFn::new_v1(|_, _, _, _| {
+            Ok((false, SupportsQueryResponse::from(vec![SupportResult::Support])))
+        }));
 
-        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "There should be no tokens for sale left");
+        let result = marketplace_view_contract_compatible(&ctx, &mut host);
+        claim_eq!(result, Ok(true), "Contract supporting CIS2 should be compatible.");
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), MockFn::new_v1(|_, _, _, _| {
+            Ok((false, SupportsQueryResponse::from(vec![SupportResult::NoSupport])))
+        }));
+
+        let result = marketplace_view_contract_compatible(&ctx, &mut host);
+        claim_eq!(result, Ok(false), "Contract not supporting CIS2 should not be compatible.");
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("supports").into(), MockFn::returning_err::<()>(CallContractError::MissingEntrypoint));
+
+        let result = marketplace_view_contract_compatible(&ctx, &mut host);
+        claim_eq!(result, Ok(false), "Contract without a supports entrypoint should not be compatible.");
     }
 
     #[concordium_test]
-    fn test_view_tokens_for_sale() {
-        let ctx = TestReceiveContext::empty();
+    fn test_view_broken_listings() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
 
         let mut state_builder = TestStateBuilder::new();
-        let mut host = TestHost::new(State::empty(&mut state_builder), state_builder);
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
 
-        host.state_mut().tokens_for_sale.insert(TOKEN1_ID, TOKEN1_PRICE);
-        
-        let result = marketplace_view_list_for_sale(&ctx, &host);
+        let token3_id = TokenIdU32(3);
+        let token4_id = TokenIdU32(4);
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(token3_id), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(token4_id), Listing { price: TOKEN1_PRICE, seller: OTHER_ACCOUNT, expiry: None });
 
-        let view = result.expect_report("View list for sale results in rejection.");
-        claim_eq!(view.tokens, vec![ViewStateToken{ id: TOKEN1_ID, price: TOKEN1_PRICE}], "Results should contain TOKEN1.");
+        // TOKEN1 is still owned, TOKEN2 was transferred away and the query for token 3 fails.
+        // OTHER_ACCOUNT revoked the marketplace as operator.
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), MockFn::new_v1(|parameter, _, _, _| {
+            let query: BalanceOfQueryParams<TokenId> = from_bytes(parameter.0).expect_report("Invalid balanceOf parameter");
+            match query.queries[0].token_id {
+                TOKEN2_ID => Ok((false, BalanceOfQueryResponse::from(vec![TokenAmountU64(0)]))),
+                TokenIdU32(3) => Err(CallContractError::LogicReject {
+                    reason: -42000001,
+                    return_value: BalanceOfQueryResponse::from(Vec::new()),
+                }),
+                _ => Ok((false, BalanceOfQueryResponse::from(vec![TokenAmountU64(1)]))),
+            }
+        }));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), MockFn::new_v1(|parameter, _, _, _| {
+            let query: OperatorOfQueryParams = from_bytes(parameter.0).expect_report("Invalid operatorOf parameter");
+            Ok((false, OperatorOfQueryResponse::from(vec![query.queries[0].owner == SELLER_ADDR])))
+        }));
+
+        let param = ViewBrokenListingsParameter {
+            contract: NFT_CONTRACT,
+            token_ids: vec![TOKEN1_ID, TOKEN2_ID, token3_id, token4_id],
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_view_broken_listings(&ctx, &mut host);
+        claim_eq!(result, Ok(vec![TOKEN2_ID, token4_id]), "Only listings that cannot be bought should be returned.");
+    }
+
+    #[concordium_test]
+    fn test_refresh_and_prune() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        let token3_id = TokenIdU32(3);
+        let token4_id = TokenIdU32(4);
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(TOKEN2_ID), listing(TOKEN1_PRICE));
+        host.state_mut().insert_listing(token(token3_id), Listing { price: TOKEN1_PRICE, seller: OTHER_ACCOUNT, expiry: None });
+        host.state_mut().insert_listing(token(token4_id), listing(TOKEN1_PRICE));
+
+        // The seller still owns TOKEN1 but no longer owns TOKEN2. OTHER_ACCOUNT revoked the marketplace as operator.
+        // The balance query for token 4 fails.
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), MockFn::new_v1(move |parameter, _, _, _| {
+            let query: BalanceOfQueryParams<TokenId> = from_bytes(parameter.0).expect_report("Invalid balanceOf parameter");
+            let token_id = query.queries[0].token_id;
+            if token_id == token4_id {
+                return Err(CallContractError::LogicReject {
+                    reason: -1,
+                    return_value: BalanceOfQueryResponse::from(Vec::new()),
+                });
+            }
+            let balance = if token_id == TOKEN2_ID { 0 } else { 1 };
+            Ok((false, BalanceOfQueryResponse::from(vec![TokenAmountU64(balance)])))
+        }));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), MockFn::new_v1(|parameter, _, _, _| {
+            let query: OperatorOfQueryParams = from_bytes(parameter.0).expect_report("Invalid operatorOf parameter");
+            Ok((false, OperatorOfQueryResponse::from(vec![query.queries[0].owner == SELLER_ADDR])))
+        }));
+
+        let param = RefreshAndPruneParameter {
+            contract: NFT_CONTRACT,
+            token_ids: vec![TOKEN1_ID, TOKEN2_ID, token3_id, token4_id],
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_refresh_and_prune(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Ok(2), "Two listings should be pruned.");
+
+        let remaining: Vec<TokenIdentifier> = host.state().tokens_for_sale.iter().map(|(token, _)| *token).collect();
+        claim_eq!(remaining, vec![token(TOKEN1_ID), token(token4_id)], "The fulfillable listing and the one that could not be queried should remain.");
     }
 
+    #[concordium_test]
+    fn test_prune_listing() {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Account(OTHER_ACCOUNT));
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+        let mut logger = TestLogger::init();
+
+        host.state_mut().insert_listing(token(TOKEN1_ID), listing(TOKEN1_PRICE));
+
+        let param = PruneListingParameter {
+            token_id: TOKEN1_ID,
+            contract: NFT_CONTRACT,
+        };
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(1));
+        let result = marketplace_prune_listing(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Ok(false), "A listing the seller still owns should be kept.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 1, "The listing should still be for sale.");
+
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(0));
+        let result = marketplace_prune_listing(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Ok(true), "A listing the seller no longer owns should be pruned.");
+        claim_eq!(host.state().tokens_for_sale.iter().count(), 0, "The stale listing should be removed.");
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&MarketplaceEvent::Withdrawn { contract: NFT_CONTRACT, token_id: TOKEN1_ID })],
+            "A Withdrawn event should be logged."
+        );
+
+        let result = marketplace_prune_listing(&ctx, &mut host, &mut logger);
+        claim_eq!(result, Err(MarketplaceError::TokenNotFound(TOKEN1_ID).into()), "An unlisted token cannot be pruned.");
+    }
 
     #[concordium_test]
-    fn test_get_listed_for_sale() {
+    fn test_view_seller_readiness() {
         let mut ctx = TestReceiveContext::empty();
+        ctx.set_self_address(MARKETPLACE_CONTRACT);
 
         let mut state_builder = TestStateBuilder::new();
-        let mut host = TestHost::new(State::empty(&mut state_builder), state_builder);
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
 
-        host.state_mut().tokens_for_sale.insert(TOKEN1_ID, TOKEN1_PRICE);
-        
-        let param = GetListedParameter{
-            token_ids: vec![TOKEN1_ID, TOKEN2_ID],
+        let param = ViewSellerReadinessParameter {
+            contract: NFT_CONTRACT,
+            token_id: TOKEN1_ID,
+            seller: OWNER,
         };
 
         let param_bytes = to_bytes(&param);
         ctx.set_parameter(&param_bytes);
 
-        let result = marketplace_get_listed_for_sale(&ctx, &host);
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("operatorOf").into(), MockFn::new_v1(|_, _, _, _| {
+            Ok((false, OperatorOfQueryResponse::from(vec![true])))
+        }));
+        host.setup_mock_entrypoint(NFT_CONTRACT, EntrypointName::new_unchecked("balanceOf").into(), mock_balance_of(1));
 
-        let view = result.expect_report("Get listed for sale results in rejection.");
-        claim_eq!(view.tokens, vec![ViewStateToken{ id: TOKEN1_ID, price: TOKEN1_PRICE}], "Results should contain only TOKEN1.");
+        let result = marketplace_view_seller_readiness(&ctx, &mut host);
+        claim_eq!(
+            result,
+            Ok(SellerReadiness { is_operator: true, balance: TokenAmountU64(1) }),
+            "Readiness should report both the operator flag and balance."
+        );
     }
-}
 
+    #[concordium_test]
+    fn test_view_price_histogram() {
+        let mut ctx = TestReceiveContext::empty();
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        for (id, price) in [(1, 50), (2, 100), (3, 150), (4, 999), (5, 1000)] {
+            host.state_mut().insert_listing(token(TokenIdU32(id)), listing(TokenAmountU64(price)));
+        }
+
+        let param = ViewPriceHistogramParameter {
+            boundaries: vec![TokenAmountU64(100), TokenAmountU64(1000)],
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_view_price_histogram(&ctx, &host);
+        claim_eq!(result, Ok(vec![1, 3, 1]), "Listings should be counted per price bucket.");
+    }
+
+    #[concordium_test]
+    fn test_view_contract_price_histogram() {
+        let mut ctx = TestReceiveContext::empty();
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        for (id, price) in [(1, 50), (2, 100), (3, 1000)] {
+            host.state_mut().insert_listing(token(TokenIdU32(id)), listing(TokenAmountU64(price)));
+        }
+        for (id, price) in [(1, 10), (2, 20), (3, 500)] {
+            let other = TokenIdentifier { contract: OTHER_NFT_CONTRACT, token_id: TokenIdU32(id) };
+            host.state_mut().insert_listing(other, listing(TokenAmountU64(price)));
+        }
+
+        let param = ViewContractPriceHistogramParameter {
+            contract: NFT_CONTRACT,
+            boundaries: vec![TokenAmountU64(100), TokenAmountU64(1000)],
+        };
+
+        let param_bytes = to_bytes(&param);
+        ctx.set_parameter(&param_bytes);
+
+        let result = marketplace_view_contract_price_histogram(&ctx, &host);
+        claim_eq!(result, Ok(vec![1, 1, 1]), "Only the listings of the given contract should be counted.");
+    }
+
+    #[concordium_test]
+    fn test_view_now() {
+        let mut ctx = TestReceiveContext::empty();
+        let now = Timestamp::from_timestamp_millis(10_000);
+        ctx.set_metadata_slot_time(now);
+
+        let mut state_builder = TestStateBuilder::new();
+        let host = TestHost::new(State::empty(&mut state_builder, OWNER), state_builder);
+
+        let result = marketplace_view_now(&ctx, &host);
+        claim_eq!(result.expect_report("View now results in rejection."), now, "Should return the slot time.");
+    }
+}